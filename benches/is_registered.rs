@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+#[cfg(target_os = "linux")]
+use protocol_handler::linux::{DesktopEntry, DesktopEntryRef};
+
+#[cfg(target_os = "linux")]
+fn desktop_file() -> String {
+    let mut body = String::from("[Desktop Entry]\nType=Application\nName=App\nExec=app %u\n");
+    for i in 0..200 {
+        body.push_str(&format!(
+            "Name[l{i}]=App {i}\nComment[l{i}]=Opens links {i}\n"
+        ));
+    }
+    body.push_str("MimeType=text/html;x-scheme-handler/http;x-scheme-handler/myapp;\n");
+    body.push_str("[Desktop Action new]\nName=New Window\nExec=app --new\n");
+    body
+}
+
+#[cfg(target_os = "linux")]
+fn bench_is_registered(c: &mut Criterion) {
+    let body: String = desktop_file();
+    c.bench_function("full parse", |b| {
+        b.iter(|| {
+            body.parse::<DesktopEntry>()
+                .unwrap()
+                .has_scheme_handler("myapp")
+        })
+    });
+    c.bench_function("scan", |b| {
+        b.iter(|| {
+            DesktopEntryRef::new(&body)
+                .unwrap()
+                .has_scheme_handler("myapp")
+        })
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bench_is_registered(_: &mut Criterion) {}
+
+criterion_group!(benches, bench_is_registered);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+// The Linux backend is the only one so far. `required-features` covers the
+// feature, the cfgs below cover the target.
+#[cfg(target_os = "linux")]
+use std::env;
+use std::process::ExitCode;
+
+#[cfg(target_os = "linux")]
+use protocol_handler::ProtocolHandler;
+
+#[cfg(target_os = "linux")]
+const USAGE: &str =
+    "usage: register <protocol> [--name <id>] [--default] [--register | --unregister]
+
+Without --register or --unregister only the current status is printed.";
+
+#[cfg(target_os = "linux")]
+enum Action {
+    Query,
+    Register,
+    Unregister,
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> ExitCode {
+    eprintln!("register only supports Linux");
+    ExitCode::FAILURE
+}
+
+#[cfg(target_os = "linux")]
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let Some(protocol_name) = args.next() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let mut name: String = protocol_name.clone();
+    let mut set_as_default: bool = false;
+    let mut action: Action = Action::Query;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => match args.next() {
+                Some(val) => name = val,
+                None => {
+                    eprintln!("--name requires a value\n\n{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--default" => set_as_default = true,
+            "--register" => action = Action::Register,
+            "--unregister" => action = Action::Unregister,
+            _ => {
+                eprintln!("unknown argument {arg}\n\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let handler: ProtocolHandler =
+        ProtocolHandler::new(name, protocol_name).set_as_default(set_as_default);
+
+    let result = match action {
+        Action::Query => Ok(()),
+        Action::Register => handler.register().map(|_| ()),
+        Action::Unregister => handler.unregister().map(|_| ()),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        return ExitCode::FAILURE;
+    }
+
+    match handler.status() {
+        Ok(status) => {
+            println!("desktop file:  {}", status.path.display());
+            println!("exists:        {}", status.exists);
+            println!("registered:    {}", status.is_registered);
+            println!("default:       {}", status.is_default);
+            println!("exec valid:    {}", status.is_exec_valid);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
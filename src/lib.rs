@@ -1,21 +1,814 @@
-use linux::LinuxError;
+#[cfg(all(target_os = "linux", feature = "linux"))]
+use std::str::FromStr;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-#[cfg(target_os = "linux")]
-mod linux;
+use indexmap::IndexMap;
+#[cfg(all(target_os = "linux", feature = "linux"))]
+use linux::{HandlerStatus, LinuxError};
 
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub mod linux;
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub use linux::LinuxError as Error;
+
+/// Returned by the cross-platform methods on targets without a backend.
+#[cfg(not(all(target_os = "linux", feature = "linux")))]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Protocol handlers are not supported on this platform")]
+    UnsupportedPlatform,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RegistrationReport {
+    pub path: PathBuf,
+    /// The desktop file did not exist before and was created by this call.
+    pub created: bool,
+    /// The desktop file carries the crate's managed marker.
+    pub managed: bool,
+    /// An existing `Exec` was replaced because the executable moved.
+    pub exec_updated: bool,
+}
+
+/// Returns the identifier of the application currently handling `scheme`.
+///
+/// The identifier is platform specific: on Linux it is the desktop file id
+/// (e.g. `firefox.desktop`) taken from `mimeapps.list`.
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub fn default_handler_for(scheme: &str) -> Result<Option<String>, Error> {
+    linux::default_handler_for(scheme)
+}
+
+/// Checks whether any installed application declares a handler for
+/// `scheme`, not only this crate's desktop files.
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub fn is_registered_anywhere(scheme: &str) -> Result<bool, Error> {
+    linux::is_registered_anywhere(scheme)
+}
+
+/// Returns the localized `Name` of the application currently handling
+/// `scheme`, for prompts like "Firefox currently opens these links".
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub fn default_handler_name(scheme: &str) -> Result<Option<String>, Error> {
+    linux::default_handler_name(scheme)
+}
+
+/// Registers each handler in turn, returning one result per handler rather
+/// than stopping at the first failure.
+pub fn register_all(handlers: &[ProtocolHandler]) -> Vec<Result<RegistrationReport, Error>> {
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    return linux::register_all(handlers);
+    #[cfg(not(all(target_os = "linux", feature = "linux")))]
+    handlers
+        .iter()
+        .map(|_| Err(Error::UnsupportedPlatform))
+        .collect()
+}
+
+/// A URL scheme such as `myapp`, validated against RFC 3986 and lowercased.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Scheme(String);
+
+impl Scheme {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+impl FromStr for Scheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !is_valid_scheme(s) {
+            return Err(Error::SchemeError(s.to_string()));
+        }
+        Ok(Scheme(s.to_ascii_lowercase()))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+impl TryFrom<&str> for Scheme {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+impl TryFrom<String> for Scheme {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Scheme> for String {
+    fn from(value: Scheme) -> Self {
+        value.0
+    }
+}
+
+impl AsRef<str> for Scheme {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Checks `s` against the RFC 3986 scheme grammar, `ALPHA *( ALPHA / DIGIT
+/// / "+" / "-" / "." )`, which includes `web+` and `ext+` forms such as
+/// `web+my-app`.
+pub const fn is_valid_scheme(s: &str) -> bool {
+    let bytes: &[u8] = s.as_bytes();
+    if bytes.is_empty() || !bytes[0].is_ascii_alphabetic() {
+        return false;
+    }
+    let mut i: usize = 1;
+    while i < bytes.len() {
+        let b: u8 = bytes[i];
+        if !(b.is_ascii_alphanumeric() || b == b'+' || b == b'-' || b == b'.') {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SymlinkPolicy {
+    #[default]
+    Follow,
+    Error,
+}
+
+/// What scanning many desktop files does when one of them cannot be parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MalformedPolicy {
+    /// Skip the file and report it alongside the results.
+    #[default]
+    Skip,
+    /// Abort the scan with the parse error.
+    Error,
+}
+
+/// How hard writes try to reach the disk before returning.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leave flushing to the OS.
+    Buffered,
+    /// `fsync` the file, and its directory when the file was created.
+    #[default]
+    Sync,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    #[default]
+    User,
+    System,
+    /// `System` when running as root, `User` otherwise.
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UrlArity {
+    #[default]
+    Single,
+    Multiple,
+}
+
+impl UrlArity {
+    pub fn field_code(&self) -> &'static str {
+        match self {
+            UrlArity::Single => "%u",
+            UrlArity::Multiple => "%U",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RegistrationConfig {
+    pub scope: Scope,
+    pub applications_dir: Option<PathBuf>,
+    pub run_db_update: bool,
+    pub create_backup: bool,
+    /// How many times a failing database update is retried.
+    pub db_update_retries: u32,
+    /// Delay before the first retry, doubled after every attempt.
+    pub db_update_backoff: Duration,
+    /// Only ever add the scheme to `MimeType`, leaving existing entries
+    /// untouched, and refuse to unregister.
+    pub append_only: bool,
+    pub durability: Durability,
+    /// Accept desktop files that are not valid UTF-8 by decoding them as
+    /// Latin-1. They are written back as UTF-8.
+    pub legacy_encoding: bool,
+    /// Semicolon-separated keys whose duplicate items are dropped on write.
+    pub dedup_keys: Vec<String>,
+    /// Mode applied to newly created desktop files, `None` leaves it to the
+    /// umask. System installs are always made readable by group and other.
+    pub file_mode: Option<u32>,
+    /// Replace an existing desktop file that cannot be parsed instead of
+    /// failing. Combine with `create_backup` to keep the old contents.
+    pub force: bool,
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        RegistrationConfig {
+            scope: Scope::default(),
+            applications_dir: None,
+            run_db_update: false,
+            create_backup: false,
+            db_update_retries: 3,
+            db_update_backoff: Duration::from_millis(100),
+            append_only: false,
+            durability: Durability::default(),
+            legacy_encoding: false,
+            dedup_keys: ["MimeType", "Keywords", "Categories"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            file_mode: Some(0o644),
+            force: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct ProtocolHandler {
     pub name: String,
     pub protocol_name: String,
+    pub set_as_default: bool,
+    pub working_dir: Option<PathBuf>,
+    pub symlink_policy: SymlinkPolicy,
+    pub dbus_activatable: bool,
+    pub extra_keys: IndexMap<String, String>,
+    pub spec_version: Option<String>,
+    pub no_url_arg: bool,
+    pub take_over_default: Option<bool>,
+    pub wrapper_command: Option<String>,
+    pub url_arity: UrlArity,
+    pub command_template: Option<String>,
+    pub generic_name: Option<String>,
+    pub localized_generic_names: IndexMap<String, String>,
+    pub host_exe: Option<PathBuf>,
+    pub snap_name: Option<String>,
+    pub dev_binary: Option<PathBuf>,
+    pub allowed_exec_prefixes: Vec<PathBuf>,
+}
+
+// `IndexMap` equality ignores order, so the maps only contribute their
+// length to the hash.
+impl Hash for ProtocolHandler {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.protocol_name.hash(state);
+        self.set_as_default.hash(state);
+        self.working_dir.hash(state);
+        self.symlink_policy.hash(state);
+        self.dbus_activatable.hash(state);
+        self.extra_keys.len().hash(state);
+        self.spec_version.hash(state);
+        self.no_url_arg.hash(state);
+        self.take_over_default.hash(state);
+        self.wrapper_command.hash(state);
+        self.url_arity.hash(state);
+        self.command_template.hash(state);
+        self.generic_name.hash(state);
+        self.localized_generic_names.len().hash(state);
+        self.host_exe.hash(state);
+        self.snap_name.hash(state);
+        self.dev_binary.hash(state);
+        self.allowed_exec_prefixes.hash(state);
+    }
 }
 
 impl ProtocolHandler {
-    #[cfg(target_os = "linux")]
-    pub fn register(&self) -> Result<(), LinuxError> {
-        linux::register(&self.name, &self.protocol_name)
+    pub fn new(name: impl Into<String>, protocol_name: impl Into<String>) -> Self {
+        ProtocolHandler {
+            name: name.into(),
+            protocol_name: protocol_name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Builds a handler from an existing desktop file: the name is taken
+    /// from the file name and the protocol from the first scheme it
+    /// declares, if any.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn try_from_desktop_file(path: &Path) -> Result<Self, LinuxError> {
+        linux::handler_from_desktop_file(path)
+    }
+
+    pub fn set_as_default(mut self, value: bool) -> Self {
+        self.set_as_default = value;
+        self
+    }
+
+    pub fn working_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.working_dir = Some(path.into());
+        self
+    }
+
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    pub fn dbus_activatable(mut self, value: bool) -> Self {
+        self.dbus_activatable = value;
+        self
+    }
+
+    pub fn extra_key(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_keys.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn spec_version(mut self, version: impl Into<String>) -> Self {
+        self.spec_version = Some(version.into());
+        self
+    }
+
+    /// Registers the scheme without passing the URL on the command line.
+    ///
+    /// The application is then responsible for retrieving the URL itself,
+    /// e.g. over IPC from an already running instance.
+    pub fn no_url_arg(mut self) -> Self {
+        self.no_url_arg = true;
+        self
+    }
+
+    /// Decides what happens when another application is already the default
+    /// handler: `true` makes this handler the default, `false` refuses to
+    /// register.
+    pub fn take_over_default(mut self, value: bool) -> Self {
+        self.take_over_default = Some(value);
+        self
+    }
+
+    /// Runs `cmd` through `/bin/sh` before executing the application, e.g.
+    /// to set up its environment.
+    pub fn wrapper_command(mut self, cmd: impl Into<String>) -> Self {
+        self.wrapper_command = Some(cmd.into());
+        self
+    }
+
+    /// Chooses between `%u` and `%U`, i.e. whether the desktop may pass
+    /// several URLs to a single invocation.
+    pub fn url_arity(mut self, value: UrlArity) -> Self {
+        self.url_arity = value;
+        self
+    }
+
+    /// Uses `path` in `Exec` instead of the current executable. Required
+    /// when running inside a container, where the executable path does not
+    /// exist on the host.
+    pub fn host_exe(mut self, path: impl Into<PathBuf>) -> Self {
+        self.host_exe = Some(path.into());
+        self
+    }
+
+    /// Points `Exec` at a build output such as `target/debug/app` for
+    /// development, taking precedence over the installed executable.
+    /// Relative paths are resolved against the current directory, which is
+    /// the package root under `cargo run`.
+    pub fn dev_binary(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dev_binary = Some(path.into());
+        self
+    }
+
+    /// Refuses to register, and reports `is_exec_valid` as false, when the
+    /// `Exec` program lies outside all of `prefixes`, e.g. `/usr` or the
+    /// install prefix.
+    pub fn allowed_exec_prefixes<P: AsRef<Path>>(mut self, prefixes: &[P]) -> Self {
+        self.allowed_exec_prefixes = prefixes.iter().map(|x| x.as_ref().to_path_buf()).collect();
+        self
+    }
+
+    /// Launches the application through `/snap/bin/<name>`. Detected from
+    /// `SNAP_NAME` when running inside a snap and neither this nor
+    /// `host_exe` is set.
+    pub fn snap_name(mut self, name: impl Into<String>) -> Self {
+        self.snap_name = Some(name.into());
+        self
+    }
+
+    /// Sets `GenericName`, e.g. "Web Browser". Existing values in the
+    /// desktop file are kept.
+    pub fn generic_name(mut self, text: impl Into<String>) -> Self {
+        self.generic_name = Some(text.into());
+        self
+    }
+
+    /// Sets `GenericName[locale]`.
+    pub fn localized_generic_name(
+        mut self,
+        locale: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Self {
+        self.localized_generic_names
+            .insert(locale.into(), text.into());
+        self
+    }
+
+    /// Uses `template` as the launch command instead of the current
+    /// executable. `%1` marks where the URL goes and must appear exactly
+    /// once; on Linux it becomes the `%u`/`%U` field code.
+    pub fn command_template(mut self, template: impl Into<String>) -> Self {
+        self.command_template = Some(template.into());
+        self
+    }
+
+    /// Registers the handler with the platform backend, or returns
+    /// `Error::UnsupportedPlatform` where there is none yet.
+    pub fn register(&self) -> Result<RegistrationReport, Error> {
+        #[cfg(all(target_os = "linux", feature = "linux"))]
+        return linux::register(self);
+        #[cfg(not(all(target_os = "linux", feature = "linux")))]
+        Err(Error::UnsupportedPlatform)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn register_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<RegistrationReport, LinuxError> {
+        linux::register_with_config(self, config)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn register_from_template(&self, template: &Path) -> Result<(), LinuxError> {
+        linux::register_from_template(self, template)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn register_from_template_with_config(
+        &self,
+        template: &Path,
+        config: &RegistrationConfig,
+    ) -> Result<RegistrationReport, LinuxError> {
+        linux::register_from_template_with_config(self, template, config)
+    }
+
+    /// Installs a PNG or SVG icon into the user's hicolor theme and
+    /// registers the handler with `Icon` pointing at it.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn register_icon_from_file(&self, icon: &Path) -> Result<(), LinuxError> {
+        linux::register_icon_from_file(self, icon)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn register_icon_from_file_with_config(
+        &self,
+        icon: &Path,
+        config: &RegistrationConfig,
+    ) -> Result<RegistrationReport, LinuxError> {
+        linux::register_icon_from_file_with_config(self, icon, config)
+    }
+
+    /// Removes the scheme from the desktop file and returns the schemes that
+    /// were actually removed, which is empty if it was not registered.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn unregister(&self) -> Result<Vec<String>, LinuxError> {
+        linux::unregister(self)
+    }
+
+    /// Deletes the desktop file and every `mimeapps.list` entry pointing at
+    /// it. Only files created by this crate are removed; returns `false`
+    /// when there was nothing to delete.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn purge(&self) -> Result<bool, LinuxError> {
+        linux::purge(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn purge_with_config(&self, config: &RegistrationConfig) -> Result<bool, LinuxError> {
+        linux::purge_with_config(self, config)
+    }
+
+    /// Returns the desktop file as `unregister` would leave it, without
+    /// writing anything.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn render_after_unregister(&self) -> Result<String, LinuxError> {
+        linux::render_after_unregister(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn render_after_unregister_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<String, LinuxError> {
+        linux::render_after_unregister_with_config(self, config)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn unregister_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<Vec<String>, LinuxError> {
+        linux::unregister_with_config(self, config)
+    }
+
+    /// Removes every scheme starting with `prefix` from the desktop file,
+    /// e.g. `suite.` for `suite.tool1` and `suite.tool2`.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn unregister_prefix(&self, prefix: &str) -> Result<Vec<String>, LinuxError> {
+        linux::unregister_prefix(self, prefix)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn unregister_prefix_with_config(
+        &self,
+        prefix: &str,
+        config: &RegistrationConfig,
+    ) -> Result<Vec<String>, LinuxError> {
+        linux::unregister_prefix_with_config(self, prefix, config)
+    }
+
+    /// Moves the desktop file to `new_name` and points any `mimeapps.list`
+    /// entries for the old id at the new one.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn rename(&self, new_name: &str) -> Result<(), LinuxError> {
+        linux::rename(self, new_name)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn rename_with_config(
+        &self,
+        new_name: &str,
+        config: &RegistrationConfig,
+    ) -> Result<(), LinuxError> {
+        linux::rename_with_config(self, new_name, config)
+    }
+
+    /// Checks whether the desktop file declares the scheme.
+    ///
+    /// Entries with `Hidden=true` count as deleted and are never registered.
+    /// `NoDisplay=true` only hides the application from menus, so such entries
+    /// still handle the scheme.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn is_registered(&self) -> Result<bool, LinuxError> {
+        linux::is_registered(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn is_registered_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<bool, LinuxError> {
+        linux::is_registered_with_config(self, config)
+    }
+
+    /// Lists every scheme the desktop file declares. Invalid schemes are
+    /// skipped.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn protocols(&self) -> Result<Vec<Scheme>, LinuxError> {
+        linux::protocols(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn protocols_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<Vec<Scheme>, LinuxError> {
+        linux::protocols_with_config(self, config)
+    }
+
+    /// Builds the argv the desktop would launch for `url` from the
+    /// registered `Exec`.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn resolve_command(&self, url: &str) -> Result<Vec<String>, LinuxError> {
+        linux::resolve_command(self, url)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn resolve_command_with_config(
+        &self,
+        url: &str,
+        config: &RegistrationConfig,
+    ) -> Result<Vec<String>, LinuxError> {
+        linux::resolve_command_with_config(self, url, config)
+    }
+
+    /// Returns whether `other`, the entry currently on disk, already matches
+    /// what registering would write, ignoring cosmetic differences such as
+    /// key order.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn equivalent_to(&self, other: &linux::DesktopEntry) -> Result<bool, LinuxError> {
+        linux::equivalent_to(self, other)
+    }
+
+    /// Reads the application's `Name`, preferring the translation for the
+    /// current locale.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn display_name(&self) -> Result<Option<String>, LinuxError> {
+        linux::display_name(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn display_name_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<Option<String>, LinuxError> {
+        linux::display_name_with_config(self, config)
+    }
+
+    /// Like `display_name`, but for an explicit locale such as
+    /// `de_DE.UTF-8` instead of the one in the environment.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn display_name_for_locale(&self, locale: &str) -> Result<Option<String>, LinuxError> {
+        linux::display_name_for_locale(self, locale)
+    }
+
+    /// Calls `callback` with the new desktop file id whenever the default
+    /// handler for the scheme changes, until the returned watcher is dropped.
+    #[cfg(all(target_os = "linux", feature = "notify"))]
+    pub fn watch<F>(&self, callback: F) -> Result<linux::DefaultWatcher, LinuxError>
+    where
+        F: FnMut(Option<String>) + Send + 'static,
+    {
+        linux::watch(self, callback)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn is_default(&self) -> Result<bool, LinuxError> {
+        linux::is_default(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn status(&self) -> Result<HandlerStatus, LinuxError> {
+        linux::status(self)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    pub fn status_with_config(
+        &self,
+        config: &RegistrationConfig,
+    ) -> Result<HandlerStatus, LinuxError> {
+        linux::status_with_config(self, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    #[test]
+    fn test_new_and_register() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = ProtocolHandler::new("myapp", String::from("myapp"))
+            .host_exe(std::env::current_exe().unwrap());
+        assert_eq!(handler.name, "myapp");
+        assert_eq!(handler.protocol_name, "myapp");
+
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        handler.register_with_config(&config).unwrap();
+        assert!(std::fs::read_to_string(dir.path().join("myapp.desktop"))
+            .unwrap()
+            .contains("MimeType=x-scheme-handler/myapp"));
+
+        assert!(handler.purge_with_config(&config).unwrap());
+        assert!(!dir.path().join("myapp.desktop").exists());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    #[test]
+    fn test_scheme_from_str() {
+        assert_eq!("MyApp".parse::<Scheme>().unwrap().as_str(), "myapp");
+        assert_eq!("web+app".parse::<Scheme>().unwrap().to_string(), "web+app");
+        assert!("1app".parse::<Scheme>().is_err());
+        assert!("my app".parse::<Scheme>().is_err());
+        assert!("".parse::<Scheme>().is_err());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    #[test]
+    fn test_scheme_conversions() {
+        let scheme = Scheme::try_from("MyApp").unwrap();
+        assert_eq!(scheme.as_ref(), "myapp");
+        assert!(Scheme::try_from(String::from("my app")).is_err());
+
+        let handler = ProtocolHandler::new("myapp", scheme);
+        assert_eq!(handler.protocol_name, "myapp");
+    }
+
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    #[test]
+    fn test_scheme_prefixed_forms() {
+        assert_eq!(
+            "web+custom".parse::<Scheme>().unwrap().as_str(),
+            "web+custom"
+        );
+        assert_eq!("ext+foo".parse::<Scheme>().unwrap().as_str(), "ext+foo");
+        assert_eq!("Web+Mail".parse::<Scheme>().unwrap().as_str(), "web+mail");
+        assert!("coap+tcp".parse::<Scheme>().is_ok());
+        assert_eq!(
+            "web+my-app".parse::<Scheme>().unwrap().as_str(),
+            "web+my-app"
+        );
+        assert!("ext+foo1".parse::<Scheme>().is_ok());
+        assert!("+web".parse::<Scheme>().is_err());
+        assert!("web+my app".parse::<Scheme>().is_err());
+    }
+
+    #[test]
+    fn test_is_valid_scheme() {
+        const { assert!(is_valid_scheme("myapp")) };
+        assert!(is_valid_scheme("a"));
+        assert!(is_valid_scheme("z39.50r"));
+        assert!(is_valid_scheme("coap+tcp"));
+        assert!(is_valid_scheme("my-app."));
+        assert!(is_valid_scheme("MyApp"));
+        assert!(is_valid_scheme("web+custom"));
+        assert!(is_valid_scheme("EXT+foo"));
+        assert!(!is_valid_scheme(""));
+        assert!(!is_valid_scheme("1app"));
+        assert!(!is_valid_scheme(".app"));
+        assert!(!is_valid_scheme("+app"));
+        assert!(!is_valid_scheme("my_app"));
+        assert!(!is_valid_scheme("my app"));
+        assert!(!is_valid_scheme("myapp:"));
+        assert!(!is_valid_scheme("caf\u{e9}"));
+        assert!(is_valid_scheme("web+my-app"));
+        assert!(is_valid_scheme("web+my.app"));
+        assert!(is_valid_scheme("ext+foo1"));
+    }
+
+    fn _assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        _assert_send_sync::<ProtocolHandler>();
+        _assert_send_sync::<SymlinkPolicy>();
+        _assert_send_sync::<RegistrationConfig>();
+        _assert_send_sync::<Error>();
+        _assert_send_sync::<RegistrationReport>();
+    }
+
+    #[test]
+    fn test_handler_set() {
+        let mut handlers: HashSet<ProtocolHandler> = HashSet::new();
+        assert!(handlers.insert(ProtocolHandler::new("myapp", "myapp")));
+        assert!(!handlers.insert(ProtocolHandler::new("myapp", "myapp")));
+        assert!(handlers.insert(ProtocolHandler::new("myapp", "other")));
+        assert!(handlers.insert(ProtocolHandler::new("myapp", "myapp").set_as_default(true)));
+
+        let first = ProtocolHandler::new("myapp", "myapp")
+            .extra_key("Terminal", "false")
+            .extra_key("NoDisplay", "true");
+        let second = ProtocolHandler::new("myapp", "myapp")
+            .extra_key("NoDisplay", "true")
+            .extra_key("Terminal", "false");
+        assert_eq!(first, second);
+        assert!(handlers.insert(first.clone()));
+        assert!(!handlers.insert(second));
+        assert!(handlers.contains(&first));
+        assert_eq!(handlers.len(), 4);
+    }
+
+    #[test]
+    fn test_register_on_all_targets() {
+        let register: fn(&ProtocolHandler) -> Result<RegistrationReport, Error> =
+            ProtocolHandler::register;
+        if cfg!(not(all(target_os = "linux", feature = "linux"))) {
+            let handler = ProtocolHandler::new("myapp", "myapp");
+            assert!(matches!(
+                register(&handler),
+                Err(Error::UnsupportedPlatform)
+            ));
+            assert!(matches!(
+                register_all(&[handler])[..],
+                [Err(Error::UnsupportedPlatform)]
+            ));
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    pub fn unregister(&self) -> Result<(), LinuxError> {
-        linux::unregister(&self.name)
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    #[test]
+    fn test_linux_send_sync() {
+        _assert_send_sync::<HandlerStatus>();
+        _assert_send_sync::<linux::DesktopEntry>();
+        _assert_send_sync::<linux::Change>();
     }
 }
@@ -0,0 +1,91 @@
+use super::{parse_error, LinuxError};
+
+const SCHEME_HANDLER_PREFIX: &str = "x-scheme-handler/";
+
+#[derive(Clone, Copy, Debug)]
+pub struct DesktopEntryRef<'a> {
+    body: &'a str,
+}
+
+impl<'a> DesktopEntryRef<'a> {
+    pub fn new(source: &'a str) -> Result<Self, LinuxError> {
+        let source: &str = source.strip_prefix('\u{feff}').unwrap_or(source);
+        let body: &str = match source.split_once('\n') {
+            Some(("[Desktop Entry]", body)) | Some(("[Desktop Entry]\r", body)) => body,
+            None if source == "[Desktop Entry]" || source.is_empty() => "",
+            _ => return Err(parse_error(1, "Not a desktop entry")),
+        };
+
+        let entry = DesktopEntryRef { body };
+        for (index, line) in entry.lines().enumerate() {
+            if !is_comment(line) && !line.contains('=') {
+                return Err(parse_error(
+                    index + 2,
+                    format!("Invalid field format: {line}"),
+                ));
+            }
+        }
+        Ok(entry)
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &'a str> {
+        self.body.lines().take_while(|x| !x.starts_with('['))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.lines()
+            .filter(|x| !is_comment(x))
+            .filter_map(|x| x.split_once('='))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.entries()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .last()
+    }
+
+    pub fn mime_types(&self) -> impl Iterator<Item = &'a str> {
+        self.get("MimeType")
+            .unwrap_or("")
+            .split(|x: char| x == ';' || x == ',' || x.is_whitespace())
+            .filter(|x| !x.is_empty())
+    }
+
+    pub fn has_scheme_handler(&self, scheme: &str) -> bool {
+        self.mime_types().any(|x| {
+            x.get(..SCHEME_HANDLER_PREFIX.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(SCHEME_HANDLER_PREFIX))
+                && x[SCHEME_HANDLER_PREFIX.len()..].eq_ignore_ascii_case(scheme)
+        })
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    line.trim().is_empty() || line.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_scheme_handler() {
+        let entry = DesktopEntryRef::new(
+            "[Desktop Entry]\n# comment\nExec=app %u\nMimeType=text/plain;x-scheme-handler/MyApp;\n[Desktop Action new]\nMimeType=x-scheme-handler/other",
+        )
+        .unwrap();
+        assert!(entry.has_scheme_handler("myapp"));
+        assert!(!entry.has_scheme_handler("other"));
+        assert!(!entry.has_scheme_handler("text"));
+        assert_eq!(entry.get("Exec"), Some("app %u"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(DesktopEntryRef::new("Exec=app").is_err());
+        assert!(DesktopEntryRef::new("[Desktop Entry]\nExec").is_err());
+        assert!(DesktopEntryRef::new("").is_ok());
+        assert!(DesktopEntryRef::new("\u{feff}[Desktop Entry]\nExec=app").is_ok());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use super::LinuxError;
+
+pub trait Environment {
+    fn var(&self, key: &str) -> Result<String, env::VarError>;
+
+    fn current_exe(&self) -> Result<PathBuf, LinuxError>;
+
+    fn current_dir(&self) -> Result<PathBuf, LinuxError>;
+
+    fn euid(&self) -> u32;
+
+    fn path_exists(&self, path: &Path) -> bool;
+
+    fn is_container(&self) -> bool {
+        self.var("container").is_ok_and(|x| !x.is_empty())
+            || self.path_exists(Path::new("/.dockerenv"))
+            || self.path_exists(Path::new("/run/.containerenv"))
+    }
+
+    fn home(&self) -> Result<String, LinuxError> {
+        Ok(self.var("HOME")?)
+    }
+
+    fn xdg_data_home(&self) -> Result<String, LinuxError> {
+        match self.var("XDG_DATA_HOME") {
+            Ok(val) if !val.is_empty() => Ok(val),
+            _ => Ok(format!("{}/.local/share", self.home()?)),
+        }
+    }
+
+    fn xdg_config_home(&self) -> Result<String, LinuxError> {
+        match self.var("XDG_CONFIG_HOME") {
+            Ok(val) if !val.is_empty() => Ok(val),
+            _ => Ok(format!("{}/.config", self.home()?)),
+        }
+    }
+
+    fn messages_locale(&self) -> Option<String> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| self.var(key).ok())
+            .find(|val| !val.is_empty())
+    }
+}
+
+pub struct ProcessEnvironment;
+
+impl Environment for ProcessEnvironment {
+    fn var(&self, key: &str) -> Result<String, env::VarError> {
+        env::var(key)
+    }
+
+    fn current_exe(&self) -> Result<PathBuf, LinuxError> {
+        Ok(env::current_exe()?)
+    }
+
+    fn current_dir(&self) -> Result<PathBuf, LinuxError> {
+        Ok(env::current_dir()?)
+    }
+
+    fn euid(&self) -> u32 {
+        // SAFETY: geteuid takes no arguments, cannot fail and touches no memory.
+        unsafe { libc::geteuid() }
+    }
+
+    fn path_exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use indexmap::IndexMap;
+
+use super::{io_error, parse_error, LinuxError};
+
+const DEFAULT_APPLICATIONS: &str = "Default Applications";
+
+#[derive(Debug, Default)]
+pub struct MimeApps {
+    // Comment and blank lines before the first group.
+    header: Vec<String>,
+    groups: IndexMap<String, Group>,
+}
+
+// Comment and blank lines are kept with the key that follows them, or as
+// trailing lines of the group, so that saving does not lose them.
+#[derive(Debug, Default)]
+struct Group {
+    entries: IndexMap<String, String>,
+    comments: IndexMap<String, Vec<String>>,
+    trailing: Vec<String>,
+}
+
+impl Group {
+    // Comments above a removed key move to the key after it.
+    fn remove(&mut self, key: &str) {
+        let Some(index) = self.entries.get_index_of(key) else {
+            return;
+        };
+        self.entries.shift_remove_index(index);
+        let Some(mut comments) = self.comments.shift_remove(key) else {
+            return;
+        };
+        let lines: &mut Vec<String> = match self.entries.get_index(index) {
+            Some((next, _)) => self.comments.entry(next.clone()).or_default(),
+            None => &mut self.trailing,
+        };
+        comments.append(lines);
+        *lines = comments;
+    }
+}
+
+impl TryFrom<String> for MimeApps {
+    type Error = LinuxError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let mut header: Vec<String> = Vec::new();
+        let mut groups: IndexMap<String, Group> = IndexMap::new();
+        let mut current: Option<String> = None;
+        let mut pending: Vec<String> = Vec::new();
+
+        for (index, raw) in s.lines().enumerate() {
+            let line: &str = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                pending.push(raw.to_string());
+                continue;
+            }
+
+            if let Some(group) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                match &current {
+                    Some(name) => groups[name].trailing.append(&mut pending),
+                    None => header.append(&mut pending),
+                }
+                groups.entry(group.to_string()).or_default();
+                current = Some(group.to_string());
+                continue;
+            }
+
+            let group: &String = match &current {
+                Some(val) => val,
+                None => return Err(parse_error(index + 1, "Entry outside of a group")),
+            };
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    let group: &mut Group = &mut groups[group];
+                    let key: String = key.trim().to_string();
+                    if !pending.is_empty() {
+                        group
+                            .comments
+                            .entry(key.clone())
+                            .or_default()
+                            .append(&mut pending);
+                    }
+                    group.entries.insert(key, value.trim().to_string());
+                }
+                None => {
+                    return Err(parse_error(
+                        index + 1,
+                        format!("Invalid field format: {line}"),
+                    ))
+                }
+            }
+        }
+        match &current {
+            Some(name) => groups[name].trailing.append(&mut pending),
+            None => header.append(&mut pending),
+        }
+
+        Ok(MimeApps { header, groups })
+    }
+}
+
+impl fmt::Display for MimeApps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.header {
+            writeln!(f, "{line}")?;
+        }
+        for (name, group) in &self.groups {
+            writeln!(f, "[{name}]")?;
+            for (key, value) in &group.entries {
+                for line in group.comments.get(key).into_iter().flatten() {
+                    writeln!(f, "{line}")?;
+                }
+                writeln!(f, "{key}={value}")?;
+            }
+            for line in &group.trailing {
+                writeln!(f, "{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MimeApps {
+    pub fn load(path: &Path) -> Result<Self, LinuxError> {
+        let mut content = String::new();
+        match File::open(path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut content).map_err(io_error(path))?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(io_error(path)(err)),
+        }
+
+        Self::try_from(content)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LinuxError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+        fs::write(path, self.to_string()).map_err(io_error(path))?;
+        Ok(())
+    }
+
+    pub fn get_default(&self, mime_type: &str) -> Option<&str> {
+        self.groups
+            .get(DEFAULT_APPLICATIONS)
+            .and_then(|group| group.entries.get(mime_type))
+            .and_then(|value| value.split(';').find(|x| !x.is_empty()))
+    }
+
+    pub fn set_default(&mut self, mime_type: &str, desktop_id: &str) {
+        self.groups
+            .entry(DEFAULT_APPLICATIONS.to_string())
+            .or_default()
+            .entries
+            .insert(mime_type.to_string(), desktop_id.to_string());
+    }
+
+    pub fn clear_default(&mut self, mime_type: &str) {
+        if let Some(group) = self.groups.get_mut(DEFAULT_APPLICATIONS) {
+            group.remove(mime_type);
+        }
+    }
+
+    pub fn remove_default(&mut self, mime_type: &str, desktop_id: &str) {
+        let Some(group) = self.groups.get_mut(DEFAULT_APPLICATIONS) else {
+            return;
+        };
+        let Some(value) = group.entries.get(mime_type) else {
+            return;
+        };
+
+        let remaining: Vec<&str> = value
+            .split(';')
+            .filter(|x| !x.is_empty() && *x != desktop_id)
+            .collect();
+        if remaining.is_empty() {
+            group.remove(mime_type);
+        } else {
+            let joined: String = remaining.join(";");
+            group.entries.insert(mime_type.to_string(), joined);
+        }
+    }
+
+    pub fn remove_desktop_id(&mut self, desktop_id: &str) -> bool {
+        let mut changed: bool = false;
+        for group in self.groups.values_mut() {
+            let mut emptied: Vec<String> = Vec::new();
+            for (key, value) in group.entries.iter_mut() {
+                if !value.split(';').any(|x| x == desktop_id) {
+                    continue;
+                }
+                let remaining: Vec<&str> = value
+                    .split(';')
+                    .filter(|x| !x.is_empty() && *x != desktop_id)
+                    .collect();
+                changed = true;
+                *value = remaining.join(";");
+                if value.is_empty() {
+                    emptied.push(key.clone());
+                }
+            }
+            for key in emptied {
+                group.remove(&key);
+            }
+        }
+        changed
+    }
+
+    pub fn rename_desktop_id(&mut self, old: &str, new: &str) -> bool {
+        let mut changed: bool = false;
+        for group in self.groups.values_mut() {
+            for value in group.entries.values_mut() {
+                if value.split(';').any(|x| x == old) {
+                    let renamed: Vec<&str> = value
+                        .split(';')
+                        .map(|x| if x == old { new } else { x })
+                        .collect();
+                    *value = renamed.join(";");
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_invalid_entry_outside_group() {
+        let content: String = "x-scheme-handler/app=app.desktop".to_string();
+        assert!(MimeApps::try_from(content).is_err())
+    }
+
+    #[test]
+    fn test_set_default_creates_group() {
+        let mut mimeapps = MimeApps::default();
+        mimeapps.set_default("x-scheme-handler/app", "app.desktop");
+        assert_eq!(
+            mimeapps.to_string(),
+            "[Default Applications]\nx-scheme-handler/app=app.desktop\n"
+        );
+    }
+
+    #[test]
+    fn test_set_default_preserves_other_groups() {
+        let content: String = "[Added Associations]\ntext/plain=editor.desktop;\n\n[Default Applications]\nx-scheme-handler/app=other.desktop\n".to_string();
+        let mut mimeapps = MimeApps::try_from(content).unwrap();
+        mimeapps.set_default("x-scheme-handler/app", "app.desktop");
+        assert_eq!(
+            mimeapps.to_string(),
+            "[Added Associations]\ntext/plain=editor.desktop;\n\n[Default Applications]\nx-scheme-handler/app=app.desktop\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_default() {
+        let content: String =
+            "[Default Applications]\nx-scheme-handler/app=app.desktop;other.desktop;".to_string();
+        let mut mimeapps = MimeApps::try_from(content).unwrap();
+        assert_eq!(
+            mimeapps.get_default("x-scheme-handler/app"),
+            Some("app.desktop")
+        );
+
+        mimeapps.remove_default("x-scheme-handler/app", "app.desktop");
+        assert_eq!(
+            mimeapps.to_string(),
+            "[Default Applications]\nx-scheme-handler/app=other.desktop\n"
+        );
+
+        mimeapps.remove_default("x-scheme-handler/app", "other.desktop");
+        assert_eq!(mimeapps.to_string(), "[Default Applications]\n");
+    }
+
+    #[test]
+    fn test_remove_desktop_id() {
+        let content: String = "[Added Associations]\nx-scheme-handler/app=other.desktop;app.desktop;\n[Default Applications]\nx-scheme-handler/app=app.desktop\ntext/plain=app.desktop.bak".to_string();
+        let mut mimeapps = MimeApps::try_from(content).unwrap();
+        assert!(mimeapps.remove_desktop_id("app.desktop"));
+        assert!(!mimeapps.remove_desktop_id("app.desktop"));
+        assert_eq!(
+            mimeapps.to_string(),
+            "[Added Associations]\nx-scheme-handler/app=other.desktop\n[Default Applications]\ntext/plain=app.desktop.bak\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_desktop_id() {
+        let content: String = "[Added Associations]\nx-scheme-handler/app=other.desktop;old.desktop;\n[Default Applications]\nx-scheme-handler/app=old.desktop\ntext/plain=old.desktop.bak".to_string();
+        let mut mimeapps = MimeApps::try_from(content).unwrap();
+        assert!(mimeapps.rename_desktop_id("old.desktop", "new.desktop"));
+        assert!(!mimeapps.rename_desktop_id("old.desktop", "new.desktop"));
+        assert_eq!(
+            mimeapps.to_string(),
+            "[Added Associations]\nx-scheme-handler/app=other.desktop;new.desktop;\n[Default Applications]\nx-scheme-handler/app=new.desktop\ntext/plain=old.desktop.bak\n"
+        );
+    }
+
+    #[test]
+    fn test_load_and_save_temp_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config").join("mimeapps.list");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "# managed by hand\n[Default Applications]\ntext/html=browser.desktop\n",
+        )
+        .unwrap();
+
+        let mut mimeapps = MimeApps::load(&path).unwrap();
+        mimeapps.set_default("x-scheme-handler/app", "app.desktop");
+        mimeapps.save(&path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# managed by hand\n[Default Applications]\ntext/html=browser.desktop\nx-scheme-handler/app=app.desktop\n"
+        );
+    }
+
+    #[test]
+    fn test_comments_survive_edits() {
+        let content: String = "# header\n\n[Default Applications]\n# mail\nx-scheme-handler/mailto=mail.desktop\n# app\nx-scheme-handler/app=app.desktop\n# last\ntext/html=app.desktop\n\n# trailing\n[Added Associations]\ntext/plain=app.desktop;\n".to_string();
+        let mut mimeapps = MimeApps::try_from(content.clone()).unwrap();
+        assert_eq!(mimeapps.to_string(), content);
+
+        mimeapps.set_default("x-scheme-handler/new", "new.desktop");
+        assert!(mimeapps.remove_desktop_id("app.desktop"));
+        assert_eq!(
+            mimeapps.to_string(),
+            "# header\n\n[Default Applications]\n# mail\nx-scheme-handler/mailto=mail.desktop\n# app\n# last\nx-scheme-handler/new=new.desktop\n\n# trailing\n[Added Associations]\n"
+        );
+
+        mimeapps.clear_default("x-scheme-handler/new");
+        assert_eq!(
+            mimeapps.to_string(),
+            "# header\n\n[Default Applications]\n# mail\nx-scheme-handler/mailto=mail.desktop\n# app\n# last\n\n# trailing\n[Added Associations]\n"
+        );
+    }
+
+    #[test]
+    fn test_load_and_save_errors_name_path() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mimeapps.list");
+        fs::create_dir(&path).unwrap();
+
+        let err = MimeApps::load(&path).unwrap_err();
+        assert!(matches!(&err, LinuxError::Io { path: x, .. } if *x == path));
+        assert!(err.to_string().starts_with(&path.display().to_string()));
+        let err = MimeApps::default().save(&path).unwrap_err();
+        assert!(matches!(err, LinuxError::Io { path: x, .. } if x == path));
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let dir = tempdir().unwrap();
+        let mimeapps = MimeApps::load(&dir.path().join("mimeapps.list")).unwrap();
+        assert_eq!(mimeapps.to_string(), "");
+    }
+}
@@ -1,59 +1,150 @@
 use std::{
-    env::{self, current_exe, var},
-    fs::{File, OpenOptions},
-    io::{self, Read},
-    os::unix::fs::FileExt,
-    str::Lines,
-    usize,
+    env::{self, var_os},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    thread,
+    time::Duration,
 };
 
 use indexmap::IndexMap;
 use thiserror::Error;
 
+use crate::{
+    is_valid_scheme, Durability, MalformedPolicy, ProtocolHandler, RegistrationConfig,
+    RegistrationReport, Scheme, Scope, SymlinkPolicy,
+};
+pub use entry_ref::DesktopEntryRef;
+pub use environment::{Environment, ProcessEnvironment};
+use mimeapps::MimeApps;
+#[cfg(feature = "notify")]
+pub use watch::{watch, DefaultWatcher};
+
+mod entry_ref;
+mod environment;
+mod mimeapps;
+#[cfg(feature = "notify")]
+mod watch;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum LinuxError {
-    #[error("{0}")]
-    ParseError(String),
+    #[error("Line {line}: {message}")]
+    ParseError { message: String, line: usize },
     #[error("{0}")]
     IoError(#[from] io::Error),
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
     #[error("{0}")]
     EnvError(#[from] env::VarError),
+    #[error("{0} is not a desktop file")]
+    NotDesktopFile(PathBuf),
+    #[error("{0} is a symlink")]
+    SymlinkError(PathBuf),
+    #[error("Invalid desktop entry key: {0}")]
+    KeyError(String),
+    #[error("Unrecognized desktop entry spec version: {0}")]
+    VersionError(String),
+    #[error("{0}")]
+    CommandError(String),
+    #[error("{0}")]
+    ExecError(String),
+    #[error("Could not create {0}: {1}")]
+    DirError(PathBuf, io::Error),
+    #[error("{0}")]
+    IconError(String),
+    #[error("Invalid scheme: {0}")]
+    SchemeError(String),
+    #[error("Running inside a container, set host_exe to the executable path on the host")]
+    ContainerError,
+    #[error("Unregistering is disabled in append-only mode")]
+    AppendOnly,
+    #[error("Scheme is already handled by {0}")]
+    SchemeAlreadyDefault(String),
+    #[error("{0} was not created by this crate")]
+    NotManaged(PathBuf),
+    #[error("Protocol handlers are not supported on this platform")]
+    UnsupportedPlatform,
+    #[error("Invalid desktop file name: {0}")]
+    InvalidName(String),
+    #[error("Invalid desktop entry: {0}")]
+    ValidationError(String),
+    #[error("{0} is outside the allowed Exec prefixes")]
+    ExecNotAllowed(PathBuf),
+    #[cfg(feature = "notify")]
+    #[error("{0}")]
+    WatchError(#[from] notify::Error),
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> LinuxError + '_ {
+    move |source| LinuxError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> LinuxError {
+    LinuxError::ParseError {
+        message: message.into(),
+        line,
+    }
+}
+
+/// Marks desktop files created by this crate.
+pub const MANAGED_KEY: &str = "X-ProtocolHandler-Managed";
+
+const SCHEME_MIME_PREFIX: &str = "x-scheme-handler/";
+const BOM: char = '\u{feff}';
+const SPEC_VERSIONS: [&str; 6] = ["1.0", "1.1", "1.2", "1.3", "1.4", "1.5"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(String, String),
+    Removed(String, String),
+    Modified(String, String, String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct HandlerStatus {
+    pub path: PathBuf,
+    pub exists: bool,
+    pub is_registered: bool,
+    pub is_default: bool,
+    pub is_exec_valid: bool,
 }
 
 #[derive(Debug)]
-struct DesktopEntry {
+pub struct ScanReport<T> {
+    pub results: Vec<T>,
+    pub errors: Vec<(PathBuf, LinuxError)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DesktopEntry {
+    bom: bool,
     data: IndexMap<String, String>,
+    comments: IndexMap<String, Vec<String>>,
+    trailing_comments: Vec<String>,
+    groups: IndexMap<String, Vec<String>>,
 }
 
 impl TryFrom<String> for DesktopEntry {
     type Error = LinuxError;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        let mut lines: Lines = s.lines();
-        match lines.next() {
-            Some(val) => {
-                if val != "[Desktop Entry]" {
-                    return Err(LinuxError::ParseError("Not a desktop entry".to_string()));
-                }
-            }
-            None => {}
-        }
-
-        let mut data: IndexMap<String, String> = IndexMap::new();
-        for line in lines {
-            let split: Vec<&str> = line.split('=').collect();
-            if split.len() != 2 {
-                return Err(LinuxError::ParseError("Invalid field format".to_string()));
-            }
-            data.insert(split[0].to_string(), split[1].to_string());
-        }
-
-        let mut exe = current_exe()?.to_string_lossy().to_string();
-        exe.push_str(" %u");
+        DesktopEntry::from_reader(s.as_bytes())
+    }
+}
 
-        data.entry("Exec".to_string()).or_insert(exe);
+impl FromStr for DesktopEntry {
+    type Err = LinuxError;
 
-        Ok(DesktopEntry { data })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DesktopEntry::from_reader(s.as_bytes())
     }
 }
 
@@ -68,35 +159,331 @@ impl TryFrom<&mut File> for DesktopEntry {
     }
 }
 
-impl ToString for DesktopEntry {
-    fn to_string(&self) -> String {
-        format!(
-            "[Desktop Entry]\n{}",
-            self.data
-                .iter()
-                .map(|(key, value)| format!("{key}={value}"))
-                .collect::<Vec<String>>()
-                .join("\n"),
-        )
+impl fmt::Display for DesktopEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines: Vec<String> = Vec::new();
+        for (key, value) in &self.data {
+            if let Some(comments) = self.comments.get(key) {
+                lines.extend(comments.iter().cloned());
+            }
+            lines.push(format!("{key}={value}"));
+        }
+        lines.extend(self.trailing_comments.iter().cloned());
+
+        if self.bom {
+            write!(f, "{BOM}")?;
+        }
+        write!(f, "[Desktop Entry]\n{}", lines.join("\n"))?;
+
+        for (name, group) in &self.groups {
+            write!(f, "\n[{name}]")?;
+            for line in group {
+                write!(f, "\n{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterates over the `[Desktop Entry]` keys and values in file order.
+pub struct Iter<'a>(indexmap::map::Iter<'a, String, String>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .next()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> IntoIterator for &'a DesktopEntry {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
 impl DesktopEntry {
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        write!(w, "{self}")
+    }
+
+    /// Like `to_string`, but rejects invalid keys and control characters.
+    /// Values are kept in their escaped on-disk form, as parsed; only raw
+    /// line breaks, which would split the entry, are written as `\n` and
+    /// `\r`.
+    pub fn serialize(&self) -> Result<String, LinuxError> {
+        let mut de: DesktopEntry = self.clone();
+        for (key, value) in de.data.iter_mut() {
+            if !is_valid_key(key) {
+                return Err(LinuxError::KeyError(key.clone()));
+            }
+            *value = escape_line_breaks(key, value)?;
+        }
+        Ok(de.to_string())
+    }
+
+    /// Compares the keys and values of both entries, ignoring key order,
+    /// comments and the order, duplicates and trailing `;` of lists.
+    pub fn equivalent_to(&self, other: &DesktopEntry) -> bool {
+        fn normalize(value: &str) -> Vec<&str> {
+            if !value.contains(';') {
+                return vec![value.trim()];
+            }
+            let mut items: Vec<&str> = value
+                .split(';')
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .collect();
+            items.sort_unstable();
+            items.dedup();
+            items
+        }
+        fn group(lines: &[String]) -> Vec<(&str, Vec<&str>)> {
+            let mut entries: Vec<(&str, Vec<&str>)> = lines
+                .iter()
+                .filter(|x| !x.trim().is_empty() && !x.starts_with('#'))
+                .filter_map(|x| x.split_once('='))
+                .map(|(key, value)| (key.trim(), normalize(value)))
+                .collect();
+            entries.sort();
+            entries
+        }
+
+        self.data.len() == other.data.len()
+            && self.data.iter().all(|(key, value)| {
+                other
+                    .data
+                    .get(key)
+                    .is_some_and(|x| normalize(x) == normalize(value))
+            })
+            && self.groups.len() == other.groups.len()
+            && self.groups.iter().all(|(name, lines)| {
+                other
+                    .groups
+                    .get(name)
+                    .is_some_and(|x| group(x) == group(lines))
+            })
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.data.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.data.iter())
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, LinuxError> {
+        Self::parse(reader, false)
+    }
+
+    pub fn from_reader_strict(reader: impl BufRead) -> Result<Self, LinuxError> {
+        Self::parse(reader, true)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>, legacy_encoding: bool) -> Result<Self, LinuxError> {
+        let content: String = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            Err(err) if legacy_encoding => {
+                log::warn!("Desktop file is not valid UTF-8, decoding it as Latin-1");
+                err.into_bytes().iter().map(|&x| x as char).collect()
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err).into()),
+        };
+
+        let mut de: DesktopEntry = Self::try_from(content)?;
+        if legacy_encoding && de.data.get("Encoding").is_some_and(|x| x == "Legacy-Mixed") {
+            de.data.insert("Encoding".to_string(), "UTF-8".to_string());
+        }
+        Ok(de)
+    }
+
+    fn parse(reader: impl BufRead, strict: bool) -> Result<Self, LinuxError> {
+        let mut bom: bool = false;
+        let mut seen_main: bool = false;
+        let mut data: IndexMap<String, String> = IndexMap::new();
+        let mut comments: IndexMap<String, Vec<String>> = IndexMap::new();
+        let mut pending: Vec<String> = Vec::new();
+        let mut groups: IndexMap<String, Vec<String>> = IndexMap::new();
+        let mut current: Option<String> = None;
+        for (index, line) in reader.lines().enumerate() {
+            let mut line: String = line?;
+            let number: usize = index + 1;
+            if index == 0 {
+                if let Some(stripped) = line.strip_prefix(BOM) {
+                    bom = true;
+                    line = stripped.to_string();
+                }
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                // Nonconforming files may put [Desktop Entry] after other groups.
+                if name == "Desktop Entry" && !seen_main {
+                    seen_main = true;
+                    current = None;
+                    continue;
+                }
+                if name == "Desktop Entry" || groups.contains_key(name) {
+                    return Err(parse_error(number, format!("Duplicate group {name}")));
+                }
+                groups.insert(name.to_string(), Vec::new());
+                current = Some(name.to_string());
+                continue;
+            }
+            if index == 0 {
+                return Err(parse_error(1, "Not a desktop entry"));
+            }
+
+            let is_comment: bool = line.trim().is_empty() || line.starts_with('#');
+            if !is_comment && !line.contains('=') {
+                return Err(parse_error(number, format!("Invalid field format: {line}")));
+            }
+            if let Some(name) = &current {
+                groups[name].push(line);
+                continue;
+            }
+            if is_comment {
+                pending.push(line);
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some(val) => val,
+                None => return Err(parse_error(number, format!("Invalid field format: {line}"))),
+            };
+            if !pending.is_empty() {
+                comments.insert(key.to_string(), std::mem::take(&mut pending));
+            }
+            data.insert(key.to_string(), value.to_string());
+        }
+        if !seen_main && (strict || !groups.is_empty()) {
+            return Err(parse_error(1, "Missing [Desktop Entry] group"));
+        }
+
+        Ok(DesktopEntry {
+            bom,
+            data,
+            comments,
+            trailing_comments: pending,
+            groups,
+        })
+    }
+
+    pub fn group_names(&self) -> Vec<&str> {
+        std::iter::once("Desktop Entry")
+            .chain(self.groups.keys().map(|x| x.as_str()))
+            .collect()
+    }
+
+    pub fn diff(&self, other: &DesktopEntry) -> Vec<Change> {
+        let mut changes: Vec<Change> = Vec::new();
+        for (key, value) in &self.data {
+            match other.data.get(key) {
+                Some(other_value) if other_value != value => changes.push(Change::Modified(
+                    key.clone(),
+                    value.clone(),
+                    other_value.clone(),
+                )),
+                Some(_) => {}
+                None => changes.push(Change::Removed(key.clone(), value.clone())),
+            }
+        }
+        for (key, value) in &other.data {
+            if !self.data.contains_key(key) {
+                changes.push(Change::Added(key.clone(), value.clone()));
+            }
+        }
+        changes
+    }
+
     fn get_mime_types(&self) -> Option<Vec<&str>> {
-        match self.data.get("MimeType") {
-            Some(val) => Some(val.split(';').filter(|x| !x.is_empty()).collect()),
-            None => None,
+        self.data.get("MimeType").map(|val| {
+            val.split(|x: char| x == ';' || x == ',' || x.is_whitespace())
+                .filter(|x| !x.is_empty())
+                .collect()
+        })
+    }
+
+    fn find_mime_type(&self, split: &Vec<&str>, mime_type: &str) -> Option<usize> {
+        split.iter().position(|x| x.eq_ignore_ascii_case(mime_type))
+    }
+
+    fn contains_mime_type(&self, mime_type: &str) -> bool {
+        match self.get_mime_types() {
+            Some(split) => self.find_mime_type(&split, mime_type).is_some(),
+            None => false,
         }
     }
 
-    fn find_mime_type(&self, split: &Vec<&str>, starts_with: &str) -> Option<usize> {
-        split.iter().position(|x| x.starts_with(starts_with))
+    pub fn has_scheme_handler(&self, scheme: &str) -> bool {
+        self.contains_mime_type(&scheme_mime_type(scheme))
+    }
+
+    pub fn schemes(&self) -> Vec<Scheme> {
+        self.scheme_names()
+            .into_iter()
+            .filter_map(|x| match x.parse::<Scheme>() {
+                Ok(scheme) => Some(scheme),
+                Err(err) => {
+                    log::warn!("Skipping {x}: {err}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn scheme_names(&self) -> Vec<&str> {
+        self.get_mime_types()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| is_scheme_mime_type(x))
+            .map(|x| &x[SCHEME_MIME_PREFIX.len()..])
+            .collect()
+    }
+
+    pub fn flatpak_id(&self) -> Option<&str> {
+        self.data
+            .get("X-Flatpak")
+            .map(|x| x.as_str())
+            .filter(|x| !x.is_empty())
+    }
+
+    pub fn mime_type(&self) -> Option<&str> {
+        self.data.get("MimeType").map(|x| x.as_str())
+    }
+
+    pub fn set_mime_type(&mut self, value: impl Into<String>) {
+        self.data.insert("MimeType".to_string(), value.into());
+    }
+
+    pub fn is_managed(&self) -> bool {
+        self.data.get(MANAGED_KEY).is_some_and(|x| x == "true")
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.data.get("Hidden").is_some_and(|x| x == "true")
     }
 
     pub fn insert_scheme_handler(&mut self, entry: String) {
         match self.get_mime_types() {
             Some(mut split) => {
-                match self.find_mime_type(&split, "x-scheme-handler/") {
+                match self.find_mime_type(&split, &entry) {
                     Some(position) => split[position] = &entry,
                     None => split.push(&entry),
                 }
@@ -108,9 +495,215 @@ impl DesktopEntry {
         }
     }
 
-    pub fn delete_scheme_handler(&mut self) {
+    pub fn dedup_lists(&mut self, keys: &[String]) {
+        for (key, value) in self.data.iter_mut() {
+            let base: &str = key.split('[').next().unwrap_or(key);
+            if !keys.iter().any(|x| x == base) {
+                continue;
+            }
+
+            let mut items: Vec<&str> = Vec::new();
+            for item in value.split(';').filter(|x| !x.is_empty()) {
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+            let mut deduped: String = items.join(";");
+            if value.ends_with(';') {
+                deduped.push(';');
+            }
+            *value = deduped;
+        }
+    }
+
+    pub fn set_scheme_handlers(&mut self, schemes: &[&str]) {
+        let mut mime_types: Vec<String> = self
+            .get_mime_types()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| !is_scheme_mime_type(x))
+            .map(|x| x.to_string())
+            .collect();
+        for scheme in schemes {
+            let mime_type: String = scheme_mime_type(scheme);
+            if !mime_types.contains(&mime_type) {
+                mime_types.push(mime_type);
+            }
+        }
+
+        if mime_types.is_empty() {
+            self.data.shift_remove("MimeType");
+        } else {
+            self.data
+                .insert("MimeType".to_string(), mime_types.join(";"));
+        }
+    }
+
+    pub fn append_scheme_handler(&mut self, entry: &str) {
+        if self.contains_mime_type(entry) {
+            return;
+        }
+        match self.data.get_mut("MimeType") {
+            Some(val) if !val.is_empty() => {
+                if !val.ends_with(';') {
+                    val.push(';');
+                }
+                val.push_str(entry);
+            }
+            _ => {
+                self.data.insert("MimeType".to_string(), entry.to_string());
+            }
+        }
+    }
+
+    /// Checks the rules `desktop-file-validate` enforces for the keys this
+    /// crate writes.
+    pub fn validate(&self) -> Result<(), LinuxError> {
+        let invalid = |message: String| Err(LinuxError::ValidationError(message));
+        if let Some(key) = self.data.keys().find(|x| !is_valid_key(x)) {
+            return Err(LinuxError::KeyError(key.clone()));
+        }
+        for key in ["Hidden", "NoDisplay", "Terminal", "DBusActivatable"] {
+            if let Some(value) = self.data.get(key) {
+                if value != "true" && value != "false" {
+                    return invalid(format!("{key} must be true or false, not {value}"));
+                }
+            }
+        }
+
+        let kind: &str = match self.data.get("Type") {
+            Some(kind) => kind,
+            None => return invalid("Missing Type".to_string()),
+        };
+        if !self.data.contains_key("Name") {
+            return invalid("Missing Name".to_string());
+        }
+        match kind {
+            "Application" => {}
+            "Link" if self.data.contains_key("URL") => return Ok(()),
+            "Link" => return invalid("Missing URL".to_string()),
+            "Directory" => return Ok(()),
+            _ => return invalid(format!("Unknown Type {kind}")),
+        }
+
+        let dbus: bool = self
+            .data
+            .get("DBusActivatable")
+            .is_some_and(|x| x == "true");
+        match self.data.get("Exec") {
+            Some(exec) if !self.scheme_names().is_empty() && !dbus => {
+                let args: Vec<String> = split_exec(&unescape_value(exec))?;
+                if !args
+                    .iter()
+                    .any(|x| ["%u", "%U", "%f", "%F"].contains(&x.as_str()))
+                {
+                    return invalid("Exec has no field code for the URL".to_string());
+                }
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None if dbus => Ok(()),
+            None => invalid("Missing Exec".to_string()),
+        }
+    }
+
+    fn exec_candidates(&self) -> Result<Vec<PathBuf>, LinuxError> {
+        let args: Vec<String> = match self.data.get("Exec") {
+            Some(exec) => split_exec(&unescape_value(exec))?,
+            None => return Ok(Vec::new()),
+        };
+        let Some(program) = args.first() else {
+            return Ok(Vec::new());
+        };
+
+        if program.contains('/') {
+            Ok(vec![PathBuf::from(program)])
+        } else {
+            match var_os("PATH") {
+                Some(paths) => Ok(env::split_paths(&paths).map(|x| x.join(program)).collect()),
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+
+    pub fn is_exec_valid(&self) -> bool {
+        self.exec_candidates()
+            .is_ok_and(|x| x.iter().any(|x| is_executable(x)))
+    }
+
+    /// Errors unless the `Exec` program, after resolving `PATH` and
+    /// symlinks, lies under one of `prefixes`. An empty list allows
+    /// anything.
+    pub fn check_exec_prefixes<P: AsRef<Path>>(&self, prefixes: &[P]) -> Result<(), LinuxError> {
+        if prefixes.is_empty() {
+            return Ok(());
+        }
+        let candidates: Vec<PathBuf> = self.exec_candidates()?;
+        let Some(program) = candidates
+            .iter()
+            .find(|x| is_executable(x))
+            .or(candidates.first())
+        else {
+            return Ok(());
+        };
+
+        let program: PathBuf = fs::canonicalize(program).unwrap_or_else(|_| program.clone());
+        let allowed: bool = prefixes.iter().any(|prefix| {
+            let prefix: &Path = prefix.as_ref();
+            program.starts_with(fs::canonicalize(prefix).unwrap_or_else(|_| prefix.to_path_buf()))
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(LinuxError::ExecNotAllowed(program))
+        }
+    }
+
+    /// Removes every scheme starting with `prefix`, which must itself be a
+    /// valid scheme so that an empty prefix cannot remove them all.
+    pub fn delete_scheme_handlers_with_prefix(&mut self, prefix: &str) -> Result<(), LinuxError> {
+        if !is_valid_scheme(prefix) {
+            return Err(LinuxError::SchemeError(prefix.to_string()));
+        }
+        let prefix: String = scheme_mime_type(prefix);
+        if let Some(split) = self.get_mime_types() {
+            let remaining: Vec<&str> = split
+                .into_iter()
+                .filter(|x| {
+                    !x.get(..prefix.len())
+                        .is_some_and(|x| x.eq_ignore_ascii_case(&prefix))
+                })
+                .collect();
+            if !remaining.is_empty() {
+                self.data
+                    .insert("MimeType".to_string(), remaining.join(";"));
+            } else {
+                self.data.shift_remove("MimeType");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn localized_name(&self, locale: Option<&str>) -> Option<&str> {
+        if let Some(locale) = locale {
+            for candidate in locale_candidates(locale) {
+                if let Some(name) = self.data.get(&format!("Name[{candidate}]")) {
+                    return Some(name);
+                }
+            }
+        }
+        self.data.get("Name").map(|x| x.as_str())
+    }
+
+    pub fn insert_working_dir(&mut self, path: &Path) {
+        self.data
+            .entry("Path".to_string())
+            .or_insert(path.to_string_lossy().to_string());
+    }
+
+    pub fn delete_scheme_handler(&mut self, entry: &str) {
         if let Some(mut split) = self.get_mime_types() {
-            if let Some(position) = self.find_mime_type(&split, "x-scheme-handler/") {
+            if let Some(position) = self.find_mime_type(&split, entry) {
                 split.remove(position);
                 if !split.is_empty() {
                     self.data.insert("MimeType".to_string(), split.join(";"));
@@ -122,114 +715,4727 @@ impl DesktopEntry {
     }
 }
 
-fn get_file(name: &String) -> Result<File, LinuxError> {
-    let home: String = var("HOME")?;
-    let path: String = format!("{home}/.local/share/applications/{}.desktop", name);
-
-    Ok(OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path)?)
+fn resolve_scope(scope: Scope, env: &dyn Environment) -> Scope {
+    match scope {
+        Scope::Auto if env.euid() == 0 => Scope::System,
+        Scope::Auto => Scope::User,
+        scope => scope,
+    }
 }
 
-pub fn register(name: &String, protocol_name: &String) -> Result<(), LinuxError> {
-    let mut file = get_file(name)?;
-    let mut de: DesktopEntry = DesktopEntry::try_from(&mut file)?;
+fn get_applications_dir(
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<PathBuf, LinuxError> {
+    if let Some(dir) = &config.applications_dir {
+        return Ok(dir.clone());
+    }
 
-    de.insert_scheme_handler(format!("x-scheme-handler/{protocol_name}"));
+    match resolve_scope(config.scope, env) {
+        Scope::System => Ok(PathBuf::from("/usr/share/applications")),
+        Scope::User | Scope::Auto => Ok(PathBuf::from(format!(
+            "{}/applications",
+            env.xdg_data_home()?
+        ))),
+    }
+}
 
-    file.set_len(0)?;
-    file.write_at(de.to_string().as_bytes(), 0)?;
+// `name` becomes a file name inside the applications dir and must not be
+// able to point anywhere else.
+fn validate_name(name: &str) -> Result<(), LinuxError> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.contains("..")
+        || name.contains(|x: char| {
+            matches!(x, '/' | '=' | ';' | '[' | ']') || x.is_whitespace() || x.is_control()
+        })
+    {
+        return Err(LinuxError::InvalidName(name.to_string()));
+    }
     Ok(())
 }
 
-pub fn unregister(name: &String) -> Result<(), LinuxError> {
-    let mut file = get_file(name)?;
-    let mut de: DesktopEntry = DesktopEntry::try_from(&mut file)?;
+fn get_path(
+    name: &str,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<PathBuf, LinuxError> {
+    validate_name(name)?;
+    Ok(get_applications_dir(config, env)?.join(format!("{name}.desktop")))
+}
 
-    de.delete_scheme_handler();
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
 
+fn check_symlink(path: &Path, symlink_policy: SymlinkPolicy) -> Result<(), LinuxError> {
+    if symlink_policy == SymlinkPolicy::Error {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_symlink() {
+                return Err(LinuxError::SymlinkError(path.to_path_buf()));
+            }
+        }
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use indexmap::indexmap;
+// Queries go through here so they never need write access or create files.
+fn open_desktop_file_readonly(
+    path: &Path,
+    symlink_policy: SymlinkPolicy,
+) -> Result<File, LinuxError> {
+    check_symlink(path, symlink_policy)?;
+    File::open(path).map_err(io_error(path))
+}
 
-    #[test]
-    fn test_invalid_entry() {
-        let content: String = "[Not Desktop Entry]".to_string();
-        assert!(
-            DesktopEntry::try_from(content).is_err(),
-            "Not a desktop entry"
-        )
+fn open_desktop_file(
+    path: &Path,
+    symlink_policy: SymlinkPolicy,
+    create: bool,
+) -> Result<File, LinuxError> {
+    check_symlink(path, symlink_policy)?;
+    if create {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| LinuxError::DirError(parent.to_path_buf(), err))?;
+        }
     }
 
-    #[test]
-    fn test_invalid_fields() {
-        let content: String = "[Desktop Entry]\nnotvalid".to_string();
-        assert!(
-            DesktopEntry::try_from(content).is_err(),
-            "Invalid field format"
-        )
-    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .truncate(false)
+        .open(path)
+        .map_err(io_error(path))
+}
 
-    #[test]
-    fn test_valid() {
-        let content: String = "[Desktop Entry]\nfield1=val1\nfield2=val2".to_string();
-        let de = DesktopEntry::try_from(content);
-        assert!(de.is_ok());
+fn is_scheme_mime_type(mime_type: &str) -> bool {
+    mime_type
+        .get(..SCHEME_MIME_PREFIX.len())
+        .is_some_and(|x| x.eq_ignore_ascii_case(SCHEME_MIME_PREFIX))
+}
 
-        let de = de.unwrap();
-        assert!(de.data.contains_key("field1"));
-        assert!(de.data.contains_key("field2"));
-    }
+fn scheme_mime_type(protocol_name: &str) -> String {
+    format!("{SCHEME_MIME_PREFIX}{}", protocol_name.to_lowercase())
+}
 
-    #[test]
-    fn test_to_string() {
-        let de: DesktopEntry = DesktopEntry {
-            data: indexmap! {
-                "field1".to_string() => "val1".to_string(),
-                "field2".to_string() => "val2".to_string(),
-            },
-        };
-        assert_eq!(de.to_string(), "[Desktop Entry]\nfield1=val1\nfield2=val2")
-    }
+fn get_mimeapps_path(env: &dyn Environment) -> Result<PathBuf, LinuxError> {
+    Ok(PathBuf::from(format!(
+        "{}/mimeapps.list",
+        env.xdg_config_home()?
+    )))
+}
 
-    #[test]
-    fn test_insert_scheme_handler() {
+fn get_mimeapps_search_paths(env: &dyn Environment) -> Result<Vec<PathBuf>, LinuxError> {
+    let mut paths: Vec<PathBuf> = vec![get_mimeapps_path(env)?];
+
+    let config_dirs: String = match env.var("XDG_CONFIG_DIRS") {
+        Ok(val) if !val.is_empty() => val,
+        _ => "/etc/xdg".to_string(),
+    };
+    paths.extend(
+        config_dirs
+            .split(':')
+            .map(|x| Path::new(x).join("mimeapps.list")),
+    );
+
+    paths.extend(
+        get_applications_search_dirs(env)?
+            .into_iter()
+            .map(|x| x.join("mimeapps.list")),
+    );
+
+    Ok(paths)
+}
+
+fn get_applications_search_dirs(env: &dyn Environment) -> Result<Vec<PathBuf>, LinuxError> {
+    let data_home: String = env.xdg_data_home()?;
+    let data_dirs: String = match env.var("XDG_DATA_DIRS") {
+        Ok(val) if !val.is_empty() => val,
+        _ => "/usr/local/share:/usr/share".to_string(),
+    };
+
+    let mut dirs: Vec<PathBuf> = std::iter::once(data_home.as_str())
+        .chain(data_dirs.split(':'))
+        .map(|x| Path::new(x).join("applications"))
+        .collect();
+    for dir in get_flatpak_exports_dirs(env)? {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    Ok(dirs)
+}
+
+// The session scans these even when they are missing from XDG_DATA_DIRS.
+fn get_flatpak_exports_dirs(env: &dyn Environment) -> Result<Vec<PathBuf>, LinuxError> {
+    Ok(vec![
+        Path::new(&env.xdg_data_home()?).join("flatpak/exports/share/applications"),
+        PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+    ])
+}
+
+fn list_desktop_files(dir: &Path) -> Result<Vec<PathBuf>, LinuxError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(io_error(dir)(err)),
+    };
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in entries {
+        let path: PathBuf = entry?.path();
+        if path.extension().is_some_and(|x| x == "desktop") && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn locale_candidates(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    let rest: &str = rest.split('.').next().unwrap_or(rest);
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+
+    let mut candidates: Vec<String> = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        candidates.push(format!("{lang}_{country}@{modifier}"));
+    }
+    if let Some(country) = country {
+        candidates.push(format!("{lang}_{country}"));
+    }
+    if let Some(modifier) = modifier {
+        candidates.push(format!("{lang}@{modifier}"));
+    }
+    candidates.push(lang.to_string());
+    candidates
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let (base, locale): (&str, Option<&str>) = match key.split_once('[') {
+        Some((base, rest)) => match rest.strip_suffix(']') {
+            Some(locale) => (base, Some(locale)),
+            None => return false,
+        },
+        None => (key, None),
+    };
+
+    let valid_base: bool =
+        !base.is_empty() && base.chars().all(|x| x.is_ascii_alphanumeric() || x == '-');
+    let valid_locale: bool = match locale {
+        Some(val) => {
+            !val.is_empty()
+                && val
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || matches!(x, '_' | '.' | '@' | '-'))
+        }
+        None => true,
+    };
+    valid_base && valid_locale
+}
+
+fn resolve_exe(exe: PathBuf) -> Result<PathBuf, LinuxError> {
+    let exe: PathBuf = fs::canonicalize(&exe).unwrap_or(exe);
+    if exe.to_string_lossy().ends_with(" (deleted)") || exe.starts_with("/proc") {
+        return Err(LinuxError::ExecError(format!(
+            "Executable path {} is not valid, supply the Exec command explicitly",
+            exe.display()
+        )));
+    }
+    Ok(exe)
+}
+
+fn quote_exec_arg(arg: &str) -> String {
+    let reserved: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    if !arg.contains(reserved) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn split_exec(exec: &str) -> Result<Vec<String>, LinuxError> {
+    let mut args: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            '"' => {
+                let arg: &mut String = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(next) if matches!(next, '"' | '`' | '$' | '\\') => arg.push(next),
+                            Some(next) => {
+                                arg.push('\\');
+                                arg.push(next);
+                            }
+                            None => break,
+                        },
+                        Some(next) => arg.push(next),
+                        None => {
+                            return Err(LinuxError::ExecError(
+                                "Unterminated quote in Exec".to_string(),
+                            ))
+                        }
+                    }
+                }
+            }
+            _ => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(arg) = current {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn escape_line_breaks(key: &str, value: &str) -> Result<String, LinuxError> {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() && c != '\t' => {
+                return Err(LinuxError::ValidationError(format!(
+                    "{key} contains the control character {c:?}"
+                )))
+            }
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(next) => {
+                unescaped.push('\\');
+                unescaped.push(next);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn expand_field_codes(
+    arg: &str,
+    url: &str,
+    de: &DesktopEntry,
+    path: &Path,
+) -> Result<String, LinuxError> {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('u') => expanded.push_str(url),
+            Some('c') => expanded.push_str(de.localized_name(None).unwrap_or_default()),
+            Some('k') => expanded.push_str(&path.to_string_lossy()),
+            // Deprecated field codes are removed from the command line.
+            Some('d' | 'D' | 'n' | 'N' | 'v' | 'm') => {}
+            Some(code @ ('f' | 'F' | 'U' | 'i')) => {
+                return Err(LinuxError::ExecError(format!(
+                    "Field code %{code} must be a separate argument"
+                )))
+            }
+            Some(code) => return Err(LinuxError::ExecError(format!("Unknown field code %{code}"))),
+            None => return Err(LinuxError::ExecError("Trailing % in Exec".to_string())),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Parses `exec` the way the spec describes: unescapes the string value,
+/// splits it into quoted arguments, then expands the field codes.
+fn expand_exec(
+    exec: &str,
+    url: &str,
+    de: &DesktopEntry,
+    path: &Path,
+) -> Result<Vec<String>, LinuxError> {
+    let mut argv: Vec<String> = Vec::new();
+    for arg in split_exec(&unescape_value(exec))? {
+        match arg.as_str() {
+            "%u" | "%U" => argv.push(url.to_string()),
+            "%f" | "%F" => {
+                if let Some(file) = url.strip_prefix("file://") {
+                    argv.push(file.to_string());
+                }
+            }
+            "%i" => {
+                if let Some(icon) = de.data.get("Icon") {
+                    argv.push("--icon".to_string());
+                    argv.push(icon.clone());
+                }
+            }
+            "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => {}
+            _ => argv.push(expand_field_codes(&arg, url, de, path)?),
+        }
+    }
+    Ok(argv)
+}
+
+fn wrap_exec(cmd: &str, exe: &str) -> Result<String, LinuxError> {
+    if cmd.contains('%') {
+        return Err(LinuxError::ExecError(
+            "Wrapper command must not contain field codes".to_string(),
+        ));
+    }
+
+    let script: String = format!("{cmd}; exec '{}' \"$@\"", exe.replace('\'', "'\\''"));
+    Ok(format!(
+        "/bin/sh -c {} _",
+        quote_exec_arg(&script).replace('\\', "\\\\")
+    ))
+}
+
+fn template_exec(template: &str, handler: &ProtocolHandler) -> Result<String, LinuxError> {
+    if template.matches("%1").count() != 1 {
+        return Err(LinuxError::ExecError(
+            "Command template must contain %1 exactly once".to_string(),
+        ));
+    }
+    Ok(template.replace("%1", handler.url_arity.field_code()))
+}
+
+fn resolve_dev_binary(path: &Path, env: &dyn Environment) -> Result<PathBuf, LinuxError> {
+    let path: PathBuf = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env.current_dir()?.join(path)
+    };
+    if !path.is_file() {
+        log::warn!("Dev binary {} does not exist", path.display());
+    }
+    Ok(path
+        .components()
+        .filter(|x| *x != std::path::Component::CurDir)
+        .collect())
+}
+
+fn snap_name(handler: &ProtocolHandler, env: &dyn Environment) -> Option<String> {
+    if handler.snap_name.is_some() || handler.host_exe.is_some() {
+        return handler.snap_name.clone();
+    }
+    // The executable inside a snap lives under a revision-specific path.
+    env.var("SNAP").ok().filter(|x| !x.is_empty())?;
+    env.var("SNAP_NAME").ok().filter(|x| !x.is_empty())
+}
+
+fn default_exec(
+    handler: &ProtocolHandler,
+    flatpak_id: Option<&str>,
+    env: &dyn Environment,
+) -> Result<String, LinuxError> {
+    if let Some(template) = &handler.command_template {
+        return template_exec(template, handler);
+    }
+    let mut exec: String = match flatpak_id {
+        // The sandboxed executable path is meaningless on the host.
+        Some(id) => format!("flatpak run {id}"),
+        None => {
+            let exe: PathBuf = match (&handler.dev_binary, &handler.host_exe) {
+                (Some(path), _) => resolve_dev_binary(path, env)?,
+                (None, host_exe) => match (host_exe, snap_name(handler, env)) {
+                    (_, Some(name)) => Path::new("/snap/bin").join(name),
+                    (Some(path), None) => path.clone(),
+                    (None, None) if env.is_container() => return Err(LinuxError::ContainerError),
+                    (None, None) => resolve_exe(env.current_exe()?)?,
+                },
+            };
+            let exe: String = exe.to_string_lossy().to_string();
+            match &handler.wrapper_command {
+                Some(cmd) => wrap_exec(cmd, &exe)?,
+                None => exe,
+            }
+        }
+    };
+    if !handler.dbus_activatable && !handler.no_url_arg {
+        exec.push(' ');
+        exec.push_str(handler.url_arity.field_code());
+    }
+    Ok(exec)
+}
+
+// A `;` or newline here would inject extra MimeType items or keys.
+fn validate_protocol_name(handler: &ProtocolHandler) -> Result<(), LinuxError> {
+    if !is_valid_scheme(&handler.protocol_name) {
+        return Err(LinuxError::SchemeError(handler.protocol_name.clone()));
+    }
+    Ok(())
+}
+
+fn prepare_entry(
+    de: &mut DesktopEntry,
+    handler: &ProtocolHandler,
+    env: &dyn Environment,
+) -> Result<(), LinuxError> {
+    let fresh: bool = de.data.is_empty();
+    if let Some(version) = &handler.spec_version {
+        if !SPEC_VERSIONS.contains(&version.as_str()) {
+            return Err(LinuxError::VersionError(version.clone()));
+        }
+        if fresh {
+            de.data.insert("Version".to_string(), version.clone());
+        }
+    }
+    // `Type` and `Name` are required, new entries get them from the id.
+    if fresh {
+        de.data
+            .insert("Type".to_string(), "Application".to_string());
+        de.data.insert("Name".to_string(), handler.name.clone());
+    }
+
+    // Files created by this crate follow the executable when it moves.
+    let refresh_exec: bool = !de.data.contains_key("Exec") || de.is_managed();
+    // Only an Exec written by this call is held to `url_arity`.
+    let exec_generated: bool = refresh_exec || handler.extra_keys.contains_key("Exec");
+    if refresh_exec && !handler.extra_keys.contains_key("Exec") {
+        let flatpak_id: Option<String> = match de.flatpak_id() {
+            Some(id) => Some(id.to_string()),
+            None => env.var("FLATPAK_ID").ok().filter(|x| !x.is_empty()),
+        };
+        de.data.insert(
+            "Exec".to_string(),
+            default_exec(handler, flatpak_id.as_deref(), env)?,
+        );
+    }
+    de.insert_scheme_handler(scheme_mime_type(&handler.protocol_name));
+
+    if handler.dbus_activatable {
+        de.data
+            .insert("DBusActivatable".to_string(), "true".to_string());
+    }
+
+    if let Some(path) = &handler.working_dir {
+        if !path.is_dir() {
+            log::warn!("Working directory {} does not exist", path.display());
+        }
+        de.insert_working_dir(path);
+    }
+
+    if let Some(text) = &handler.generic_name {
+        de.data
+            .entry("GenericName".to_string())
+            .or_insert(text.clone());
+    }
+    for (locale, text) in &handler.localized_generic_names {
+        let key: String = format!("GenericName[{locale}]");
+        if !is_valid_key(&key) {
+            return Err(LinuxError::KeyError(key));
+        }
+        de.data.entry(key).or_insert(text.clone());
+    }
+
+    for (key, value) in &handler.extra_keys {
+        if !is_valid_key(key) {
+            return Err(LinuxError::KeyError(key.clone()));
+        }
+        de.data.insert(key.clone(), value.clone());
+    }
+
+    if handler.no_url_arg {
+        if let Some(exec) = de.data.get("Exec") {
+            if exec.contains("%u") || exec.contains("%U") {
+                return Err(LinuxError::ExecError(
+                    "Exec contains a URL field code but no_url_arg is set".to_string(),
+                ));
+            }
+        }
+    } else if !handler.dbus_activatable && exec_generated {
+        let code: &str = handler.url_arity.field_code();
+        if let Some(exec) = de.data.get("Exec") {
+            if exec.matches(code).count() != 1 {
+                return Err(LinuxError::ExecError(format!(
+                    "Exec must contain {code} exactly once"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_entry(
+    path: &Path,
+    file: &mut File,
+    config: &RegistrationConfig,
+) -> Result<DesktopEntry, LinuxError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    file.read_to_end(&mut bytes).map_err(io_error(path))?;
+    DesktopEntry::from_bytes(bytes, config.legacy_encoding)
+}
+
+fn is_malformed(err: &LinuxError) -> bool {
+    match err {
+        LinuxError::ParseError { .. } => true,
+        LinuxError::IoError(err) => err.kind() == io::ErrorKind::InvalidData,
+        _ => false,
+    }
+}
+
+fn write_entry(
+    path: &Path,
+    file: &File,
+    de: &DesktopEntry,
+    durability: Durability,
+) -> Result<(), LinuxError> {
+    let write = || -> io::Result<()> {
+        file.set_len(0)?;
+        let mut writer = BufWriter::new(file);
+        writer.seek(SeekFrom::Start(0))?;
+        de.write_to(&mut writer)?;
+        writer.flush()?;
+        if durability == Durability::Sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    };
+    write().map_err(io_error(path))
+}
+
+fn set_file_mode(
+    path: &Path,
+    file: &File,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<(), LinuxError> {
+    let mut mode: Option<u32> = config.file_mode;
+    if resolve_scope(config.scope, env) == Scope::System {
+        let current: u32 = file
+            .metadata()
+            .map_err(io_error(path))?
+            .permissions()
+            .mode();
+        mode = Some(mode.unwrap_or(current) | 0o044);
+    }
+    if let Some(mode) = mode {
+        file.set_permissions(fs::Permissions::from_mode(mode))
+            .map_err(io_error(path))?;
+    }
+    Ok(())
+}
+
+fn sync_dir(path: &Path, durability: Durability) -> Result<(), LinuxError> {
+    if durability == Durability::Sync {
+        if let Some(parent) = path.parent() {
+            File::open(parent)
+                .and_then(|dir| dir.sync_all())
+                .map_err(io_error(parent))?;
+        }
+    }
+    Ok(())
+}
+
+fn check_default_conflict(paths: &[PathBuf], handler: &ProtocolHandler) -> Result<(), LinuxError> {
+    if handler.take_over_default != Some(false) {
+        return Ok(());
+    }
+
+    match default_handler_in(paths, &handler.protocol_name)? {
+        Some(desktop_id) if desktop_id != format!("{}.desktop", handler.name) => {
+            Err(LinuxError::SchemeAlreadyDefault(desktop_id))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn update_default_at(path: &Path, handler: &ProtocolHandler) -> Result<(), LinuxError> {
+    if handler.set_as_default || handler.take_over_default == Some(true) {
+        let mut mimeapps: MimeApps = MimeApps::load(path)?;
+        mimeapps.set_default(
+            &scheme_mime_type(&handler.protocol_name),
+            &format!("{}.desktop", handler.name),
+        );
+        mimeapps.save(path)?;
+    }
+    Ok(())
+}
+
+fn run_with_retries(
+    command: &mut Command,
+    retries: u32,
+    backoff: Duration,
+) -> Result<(), LinuxError> {
+    let mut delay: Duration = backoff;
+    let mut attempt: u32 = 0;
+    loop {
+        match command.status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) if attempt >= retries => {
+                return Err(LinuxError::CommandError(format!(
+                    "{} exited with {status}",
+                    command.get_program().to_string_lossy()
+                )))
+            }
+            Ok(status) => {
+                log::warn!(
+                    "{} exited with {status}, retrying in {delay:?}",
+                    command.get_program().to_string_lossy()
+                );
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::warn!(
+                    "{} is not installed, skipping",
+                    command.get_program().to_string_lossy()
+                );
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+fn update_database(dir: &Path, config: &RegistrationConfig) -> Result<(), LinuxError> {
+    run_with_retries(
+        Command::new("update-desktop-database").arg(dir),
+        config.db_update_retries,
+        config.db_update_backoff,
+    )
+}
+
+pub fn register(handler: &ProtocolHandler) -> Result<RegistrationReport, LinuxError> {
+    register_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn register_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<RegistrationReport, LinuxError> {
+    register_with_env(handler, config, &ProcessEnvironment)
+}
+
+pub fn register_all(handlers: &[ProtocolHandler]) -> Vec<Result<RegistrationReport, LinuxError>> {
+    register_all_with_config(handlers, &RegistrationConfig::default())
+}
+
+/// Registers every handler, carrying on past failures. With
+/// `run_db_update` the database is updated once at the end instead of
+/// after each handler; a failed update is logged.
+pub fn register_all_with_config(
+    handlers: &[ProtocolHandler],
+    config: &RegistrationConfig,
+) -> Vec<Result<RegistrationReport, LinuxError>> {
+    register_all_with_env(handlers, config, &ProcessEnvironment)
+}
+
+fn register_all_with_env(
+    handlers: &[ProtocolHandler],
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Vec<Result<RegistrationReport, LinuxError>> {
+    let deferred = RegistrationConfig {
+        run_db_update: false,
+        ..config.clone()
+    };
+    let results: Vec<Result<RegistrationReport, LinuxError>> = handlers
+        .iter()
+        .map(|handler| register_with_env(handler, &deferred, env))
+        .collect();
+
+    if config.run_db_update && results.iter().any(|x| x.is_ok()) {
+        if let Err(err) =
+            get_applications_dir(config, env).and_then(|dir| update_database(&dir, config))
+        {
+            log::warn!("Could not update the desktop database: {err}");
+        }
+    }
+    results
+}
+
+fn register_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<RegistrationReport, LinuxError> {
+    register_entry_with_env(handler, None, config, env)
+}
+
+// `template`, when given, replaces the entry read from the existing file.
+fn register_entry_with_env(
+    handler: &ProtocolHandler,
+    template: Option<DesktopEntry>,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<RegistrationReport, LinuxError> {
+    validate_protocol_name(handler)?;
+    check_default_conflict(&get_mimeapps_search_paths(env)?, handler)?;
+
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let created: bool = !path.exists();
+    let mut file = open_desktop_file(&path, handler.symlink_policy, true)?;
+    // A failed first registration must not leave an empty, unmarked file.
+    let (managed, exec_updated): (bool, bool) =
+        match write_registration(&path, &mut file, created, template, handler, config, env) {
+            Ok(val) => val,
+            Err(err) => {
+                if created {
+                    fs::remove_file(&path).map_err(io_error(&path))?;
+                }
+                return Err(err);
+            }
+        };
+
+    if config.run_db_update {
+        update_database(&get_applications_dir(config, env)?, config)?;
+    }
+    update_default_at(&get_mimeapps_path(env)?, handler)?;
+
+    Ok(RegistrationReport {
+        path,
+        created,
+        managed,
+        exec_updated,
+    })
+}
+
+fn write_registration(
+    path: &Path,
+    file: &mut File,
+    created: bool,
+    template: Option<DesktopEntry>,
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<(bool, bool), LinuxError> {
+    let mut de: DesktopEntry = match read_entry(path, file, config) {
+        Ok(de) => de,
+        Err(err) if config.force && is_malformed(&err) => {
+            log::warn!("Replacing unparseable {}: {err}", path.display());
+            DesktopEntry::default()
+        }
+        Err(err) => return Err(err),
+    };
+    let mime_types: Option<String> = de.data.get("MimeType").cloned();
+    let exec: Option<String> = de.data.get("Exec").cloned();
+    if let Some(template) = template {
+        de = template;
+    }
+
+    prepare_entry(&mut de, handler, env)?;
+    de.check_exec_prefixes(&handler.allowed_exec_prefixes)?;
+    let exec_updated: bool = exec.is_some() && de.data.get("Exec") != exec.as_ref();
+
+    if config.append_only {
+        if let Some(val) = mime_types {
+            de.data.insert("MimeType".to_string(), val);
+        }
+        de.append_scheme_handler(&scheme_mime_type(&handler.protocol_name));
+    }
+    if created {
+        de.data.insert(MANAGED_KEY.to_string(), "true".to_string());
+    }
+    if !config.append_only {
+        de.dedup_lists(&config.dedup_keys);
+    }
+
+    // Leave the file, and its mtime, alone when nothing changed.
+    if fs::read(path).map_err(io_error(path))? != de.to_string().as_bytes() {
+        if config.create_backup && file.metadata().map_err(io_error(path))?.len() > 0 {
+            let backup: PathBuf = path.with_extension("desktop.bak");
+            fs::copy(path, &backup).map_err(io_error(&backup))?;
+        }
+        write_entry(path, file, &de, config.durability)?;
+    }
+    if created {
+        set_file_mode(path, file, config, env)?;
+        sync_dir(path, config.durability)?;
+    }
+    Ok((de.is_managed(), exec_updated))
+}
+
+pub fn register_from_template(
+    handler: &ProtocolHandler,
+    template: &Path,
+) -> Result<(), LinuxError> {
+    register_from_template_with_config(handler, template, &RegistrationConfig::default())
+        .map(|_| ())
+}
+
+pub fn register_from_template_with_config(
+    handler: &ProtocolHandler,
+    template: &Path,
+    config: &RegistrationConfig,
+) -> Result<RegistrationReport, LinuxError> {
+    register_from_template_with_env(handler, template, config, &ProcessEnvironment)
+}
+
+fn register_from_template_with_env(
+    handler: &ProtocolHandler,
+    template: &Path,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<RegistrationReport, LinuxError> {
+    let mut file = File::open(template).map_err(io_error(template))?;
+    let de: DesktopEntry = read_entry(template, &mut file, config)?;
+    register_entry_with_env(handler, Some(de), config, env)
+}
+
+fn png_size(path: &Path) -> Result<(u32, u32), LinuxError> {
+    let mut header = [0u8; 24];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(io_error(path))?;
+    if header[..8] != *b"\x89PNG\r\n\x1a\n" || header[12..16] != *b"IHDR" {
+        return Err(LinuxError::IconError(format!(
+            "{} is not a PNG file",
+            path.display()
+        )));
+    }
+
+    let width: u32 = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+    let height: u32 = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+    Ok((width, height))
+}
+
+fn install_icon(icon: &Path, name: &str, env: &dyn Environment) -> Result<PathBuf, LinuxError> {
+    validate_name(name)?;
+    let (size, extension): (String, &str) = match icon.extension().and_then(|x| x.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => {
+            let (width, height) = png_size(icon)?;
+            (format!("{width}x{height}"), "png")
+        }
+        Some(ext) if ext.eq_ignore_ascii_case("svg") => ("scalable".to_string(), "svg"),
+        _ => {
+            return Err(LinuxError::IconError(format!(
+                "Unsupported icon format: {}",
+                icon.display()
+            )))
+        }
+    };
+
+    let theme_dir: PathBuf = PathBuf::from(format!("{}/icons/hicolor", env.xdg_data_home()?));
+    let dir: PathBuf = theme_dir.join(size).join("apps");
+    fs::create_dir_all(&dir).map_err(|err| LinuxError::DirError(dir.clone(), err))?;
+    fs::copy(icon, dir.join(format!("{name}.{extension}"))).map_err(io_error(icon))?;
+    Ok(theme_dir)
+}
+
+// The cache only speeds up lookups, so a failed update is logged rather than
+// undoing an icon and entry that are already in place.
+fn update_icon_cache(theme_dir: &Path) {
+    match Command::new("gtk-update-icon-cache")
+        .arg("-f")
+        .arg("-t")
+        .arg(theme_dir)
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("gtk-update-icon-cache exited with {status}"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            log::warn!("gtk-update-icon-cache is not installed, skipping")
+        }
+        Err(err) => log::warn!("Could not run gtk-update-icon-cache: {err}"),
+    }
+}
+
+pub fn register_icon_from_file(handler: &ProtocolHandler, icon: &Path) -> Result<(), LinuxError> {
+    register_icon_from_file_with_config(handler, icon, &RegistrationConfig::default()).map(|_| ())
+}
+
+pub fn register_icon_from_file_with_config(
+    handler: &ProtocolHandler,
+    icon: &Path,
+    config: &RegistrationConfig,
+) -> Result<RegistrationReport, LinuxError> {
+    register_icon_with_env(handler, icon, config, &ProcessEnvironment)
+}
+
+fn register_icon_with_env(
+    handler: &ProtocolHandler,
+    icon: &Path,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<RegistrationReport, LinuxError> {
+    let theme_dir: PathBuf = install_icon(icon, &handler.name, env)?;
+    let with_icon: ProtocolHandler = handler.clone().extra_key("Icon", handler.name.clone());
+    let report: RegistrationReport = register_with_env(&with_icon, config, env)?;
+    update_icon_cache(&theme_dir);
+    Ok(report)
+}
+
+pub fn unregister(handler: &ProtocolHandler) -> Result<Vec<String>, LinuxError> {
+    unregister_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn unregister_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<Vec<String>, LinuxError> {
+    unregister_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn edit_schemes(
+    path: &Path,
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    edit: impl FnOnce(&mut DesktopEntry) -> Result<(), LinuxError>,
+) -> Result<Vec<String>, LinuxError> {
+    let mut file = match open_desktop_file(path, handler.symlink_policy, false) {
+        Ok(file) => file,
+        Err(LinuxError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(err) => return Err(err),
+    };
+    let mut de: DesktopEntry = read_entry(path, &mut file, config)?;
+    let before: Vec<String> = de.scheme_names().iter().map(|x| x.to_string()).collect();
+    edit(&mut de)?;
+    let after: Vec<&str> = de.scheme_names();
+
+    let removed: Vec<String> = before
+        .into_iter()
+        .filter(|x| !after.contains(&x.as_str()))
+        .collect();
+    if !removed.is_empty() {
+        write_entry(path, &file, &de, config.durability)?;
+    }
+    Ok(removed)
+}
+
+fn unregister_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Vec<String>, LinuxError> {
+    if config.append_only {
+        return Err(LinuxError::AppendOnly);
+    }
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let mime_type: String = scheme_mime_type(&handler.protocol_name);
+
+    let removed: Vec<String> = edit_schemes(&path, handler, config, |de| {
+        de.delete_scheme_handler(&mime_type);
+        Ok(())
+    })?;
+
+    if handler.set_as_default {
+        let path: PathBuf = get_mimeapps_path(env)?;
+        let mut mimeapps: MimeApps = MimeApps::load(&path)?;
+        mimeapps.remove_default(&mime_type, &format!("{}.desktop", handler.name));
+        mimeapps.save(&path)?;
+    }
+    Ok(removed)
+}
+
+pub fn purge(handler: &ProtocolHandler) -> Result<bool, LinuxError> {
+    purge_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn purge_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<bool, LinuxError> {
+    purge_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn purge_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<bool, LinuxError> {
+    if config.append_only {
+        return Err(LinuxError::AppendOnly);
+    }
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let mut file = match open_desktop_file_readonly(&path, handler.symlink_policy) {
+        Ok(file) => file,
+        Err(LinuxError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            return Ok(false)
+        }
+        Err(err) => return Err(err),
+    };
+    if !read_entry(&path, &mut file, config)?.is_managed() {
+        return Err(LinuxError::NotManaged(path));
+    }
+    fs::remove_file(&path).map_err(io_error(&path))?;
+
+    let mimeapps_path: PathBuf = get_mimeapps_path(env)?;
+    let mut mimeapps: MimeApps = MimeApps::load(&mimeapps_path)?;
+    if mimeapps.remove_desktop_id(&format!("{}.desktop", handler.name)) {
+        mimeapps.save(&mimeapps_path)?;
+    }
+
+    if config.run_db_update {
+        update_database(&get_applications_dir(config, env)?, config)?;
+    }
+    Ok(true)
+}
+
+pub fn render_after_unregister(handler: &ProtocolHandler) -> Result<String, LinuxError> {
+    render_after_unregister_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn render_after_unregister_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<String, LinuxError> {
+    render_after_unregister_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn render_after_unregister_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<String, LinuxError> {
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let mut file: File = File::open(&path).map_err(io_error(&path))?;
+    let mut de: DesktopEntry = read_entry(&path, &mut file, config)?;
+    de.delete_scheme_handler(&scheme_mime_type(&handler.protocol_name));
+    Ok(de.to_string())
+}
+
+pub fn unregister_prefix(
+    handler: &ProtocolHandler,
+    prefix: &str,
+) -> Result<Vec<String>, LinuxError> {
+    unregister_prefix_with_config(handler, prefix, &RegistrationConfig::default())
+}
+
+pub fn unregister_prefix_with_config(
+    handler: &ProtocolHandler,
+    prefix: &str,
+    config: &RegistrationConfig,
+) -> Result<Vec<String>, LinuxError> {
+    unregister_prefix_with_env(handler, prefix, config, &ProcessEnvironment)
+}
+
+fn unregister_prefix_with_env(
+    handler: &ProtocolHandler,
+    prefix: &str,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Vec<String>, LinuxError> {
+    if config.append_only {
+        return Err(LinuxError::AppendOnly);
+    }
+    if !is_valid_scheme(prefix) {
+        return Err(LinuxError::SchemeError(prefix.to_string()));
+    }
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    edit_schemes(&path, handler, config, |de| {
+        de.delete_scheme_handlers_with_prefix(prefix)
+    })
+}
+
+pub fn rename(handler: &ProtocolHandler, new_name: &str) -> Result<(), LinuxError> {
+    rename_with_config(handler, new_name, &RegistrationConfig::default())
+}
+
+pub fn rename_with_config(
+    handler: &ProtocolHandler,
+    new_name: &str,
+    config: &RegistrationConfig,
+) -> Result<(), LinuxError> {
+    rename_with_env(handler, new_name, config, &ProcessEnvironment)
+}
+
+fn rename_with_env(
+    handler: &ProtocolHandler,
+    new_name: &str,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<(), LinuxError> {
+    let old_path: PathBuf = get_path(&handler.name, config, env)?;
+    let new_path: PathBuf = get_path(new_name, config, env)?;
+    if new_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", new_path.display()),
+        )
+        .into());
+    }
+    fs::rename(&old_path, &new_path).map_err(io_error(&old_path))?;
+    sync_dir(&new_path, config.durability)?;
+
+    let path: PathBuf = get_mimeapps_path(env)?;
+    let mut mimeapps: MimeApps = MimeApps::load(&path)?;
+    let old_id: String = format!("{}.desktop", handler.name);
+    let new_id: String = format!("{new_name}.desktop");
+    if mimeapps.rename_desktop_id(&old_id, &new_id) {
+        mimeapps.save(&path)?;
+    }
+    Ok(())
+}
+
+pub fn is_registered(handler: &ProtocolHandler) -> Result<bool, LinuxError> {
+    is_registered_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn is_registered_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<bool, LinuxError> {
+    is_registered_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn is_registered_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<bool, LinuxError> {
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    match open_desktop_file_readonly(&path, handler.symlink_policy) {
+        Ok(mut file) => {
+            let mut body = String::new();
+            file.read_to_string(&mut body).map_err(io_error(&path))?;
+            if declares_scheme(&body, &handler.protocol_name)? {
+                return Ok(true);
+            }
+        }
+        Err(LinuxError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    for dir in get_flatpak_exports_dirs(env)? {
+        let path: PathBuf = dir.join(format!("{}.desktop", handler.name));
+        let body: String = match fs::read_to_string(&path) {
+            Ok(body) => body,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(io_error(&path)(err)),
+        };
+        if declares_scheme(&body, &handler.protocol_name)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Scans the MimeType value without building a `DesktopEntry`, falling back
+// to a full parse for files the scanner rejects, e.g. with the main group
+// not first.
+fn declares_scheme(body: &str, scheme: &str) -> Result<bool, LinuxError> {
+    match DesktopEntryRef::new(body) {
+        Ok(entry) => Ok(entry.has_scheme_handler(scheme) && entry.get("Hidden") != Some("true")),
+        Err(_) => {
+            let de: DesktopEntry = DesktopEntry::try_from(body.to_string())?;
+            Ok(de.has_scheme_handler(scheme) && !de.is_hidden())
+        }
+    }
+}
+
+pub fn handler_from_desktop_file(path: &Path) -> Result<ProtocolHandler, LinuxError> {
+    let name: String = match path.file_stem() {
+        Some(stem) if path.extension().is_some_and(|x| x == "desktop") => {
+            stem.to_string_lossy().to_string()
+        }
+        _ => return Err(LinuxError::NotDesktopFile(path.to_path_buf())),
+    };
+    let de: DesktopEntry =
+        DesktopEntry::from_reader(BufReader::new(File::open(path).map_err(io_error(path))?))?;
+    let protocol_name: String = de
+        .schemes()
+        .first()
+        .map(|x| x.to_string())
+        .unwrap_or_default();
+
+    Ok(ProtocolHandler::new(name, protocol_name))
+}
+
+pub fn protocols(handler: &ProtocolHandler) -> Result<Vec<Scheme>, LinuxError> {
+    protocols_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn protocols_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<Vec<Scheme>, LinuxError> {
+    protocols_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn protocols_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Vec<Scheme>, LinuxError> {
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let file: File = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(io_error(&path)(err)),
+    };
+
+    Ok(DesktopEntry::from_reader(BufReader::new(file))?.schemes())
+}
+
+fn enumerate_scheme_handlers_in(
+    dirs: &[PathBuf],
+    policy: MalformedPolicy,
+) -> Result<ScanReport<(String, String)>, LinuxError> {
+    let mut report = ScanReport {
+        results: Vec::new(),
+        errors: Vec::new(),
+    };
+    for dir in dirs {
+        for path in list_desktop_files(dir)? {
+            let de: DesktopEntry = match File::open(&path)
+                .map_err(io_error(&path))
+                .and_then(|file| DesktopEntry::from_reader(BufReader::new(file)))
+            {
+                Ok(de) => de,
+                Err(err) if policy == MalformedPolicy::Error => return Err(err),
+                Err(err) => {
+                    log::warn!("Skipping {}: {err}", path.display());
+                    report.errors.push((path, err));
+                    continue;
+                }
+            };
+
+            let desktop_id: String = path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default();
+            for scheme in de.scheme_names() {
+                report
+                    .results
+                    .push((scheme.to_string(), desktop_id.clone()));
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn is_registered_anywhere_in(dirs: &[PathBuf], scheme: &str) -> Result<bool, LinuxError> {
+    Ok(enumerate_scheme_handlers_in(dirs, MalformedPolicy::Skip)?
+        .results
+        .iter()
+        .any(|(x, _)| x.eq_ignore_ascii_case(scheme)))
+}
+
+pub fn is_registered_anywhere(scheme: &str) -> Result<bool, LinuxError> {
+    is_registered_anywhere_in(&get_applications_search_dirs(&ProcessEnvironment)?, scheme)
+}
+
+fn scheme_handler_map_in(dirs: &[PathBuf]) -> Result<IndexMap<String, Vec<String>>, LinuxError> {
+    let mut map: IndexMap<String, Vec<String>> = IndexMap::new();
+    for (scheme, desktop_id) in enumerate_scheme_handlers_in(dirs, MalformedPolicy::Skip)?.results {
+        let ids: &mut Vec<String> = map.entry(scheme.to_ascii_lowercase()).or_default();
+        if !ids.contains(&desktop_id) {
+            ids.push(desktop_id);
+        }
+    }
+    Ok(map)
+}
+
+/// Maps each scheme to the desktop ids declaring it, in search path order.
+pub fn scheme_handler_map() -> Result<IndexMap<String, Vec<String>>, LinuxError> {
+    scheme_handler_map_in(&get_applications_search_dirs(&ProcessEnvironment)?)
+}
+
+fn list_managed_entries_in(dirs: &[PathBuf]) -> Result<Vec<PathBuf>, LinuxError> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        for path in list_desktop_files(dir)? {
+            match File::open(&path)
+                .map_err(io_error(&path))
+                .and_then(|file| DesktopEntry::from_reader(BufReader::new(file)))
+            {
+                Ok(de) if de.is_managed() => paths.push(path),
+                Ok(_) => {}
+                Err(err) => log::warn!("Skipping {}: {err}", path.display()),
+            }
+        }
+    }
+    Ok(paths)
+}
+
+pub fn list_managed_entries() -> Result<Vec<PathBuf>, LinuxError> {
+    list_managed_entries_in(&get_applications_search_dirs(&ProcessEnvironment)?)
+}
+
+pub fn enumerate_scheme_handlers() -> Result<Vec<(String, String)>, LinuxError> {
+    Ok(enumerate_scheme_handlers_with_policy(MalformedPolicy::Skip)?.results)
+}
+
+pub fn enumerate_scheme_handlers_with_policy(
+    policy: MalformedPolicy,
+) -> Result<ScanReport<(String, String)>, LinuxError> {
+    enumerate_scheme_handlers_in(&get_applications_search_dirs(&ProcessEnvironment)?, policy)
+}
+
+pub fn resolve_command(handler: &ProtocolHandler, url: &str) -> Result<Vec<String>, LinuxError> {
+    resolve_command_with_config(handler, url, &RegistrationConfig::default())
+}
+
+pub fn resolve_command_with_config(
+    handler: &ProtocolHandler,
+    url: &str,
+    config: &RegistrationConfig,
+) -> Result<Vec<String>, LinuxError> {
+    resolve_command_with_env(handler, url, config, &ProcessEnvironment)
+}
+
+fn resolve_command_with_env(
+    handler: &ProtocolHandler,
+    url: &str,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Vec<String>, LinuxError> {
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let de: DesktopEntry =
+        DesktopEntry::from_reader(BufReader::new(File::open(&path).map_err(io_error(&path))?))?;
+    match de.data.get("Exec") {
+        Some(exec) => expand_exec(exec, url, &de, &path),
+        None => Err(LinuxError::ExecError(
+            "Desktop file has no Exec".to_string(),
+        )),
+    }
+}
+
+pub fn equivalent_to(handler: &ProtocolHandler, other: &DesktopEntry) -> Result<bool, LinuxError> {
+    equivalent_to_with_env(handler, other, &ProcessEnvironment)
+}
+
+fn equivalent_to_with_env(
+    handler: &ProtocolHandler,
+    other: &DesktopEntry,
+    env: &dyn Environment,
+) -> Result<bool, LinuxError> {
+    validate_protocol_name(handler)?;
+    let mut desired: DesktopEntry = other.clone();
+    prepare_entry(&mut desired, handler, env)?;
+    Ok(desired.equivalent_to(other))
+}
+
+pub fn display_name(handler: &ProtocolHandler) -> Result<Option<String>, LinuxError> {
+    display_name_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn display_name_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<Option<String>, LinuxError> {
+    display_name_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn display_name_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Option<String>, LinuxError> {
+    display_name_for_locale_with_env(handler, env.messages_locale().as_deref(), config, env)
+}
+
+pub fn display_name_for_locale(
+    handler: &ProtocolHandler,
+    locale: &str,
+) -> Result<Option<String>, LinuxError> {
+    display_name_for_locale_with_env(
+        handler,
+        Some(locale),
+        &RegistrationConfig::default(),
+        &ProcessEnvironment,
+    )
+}
+
+fn display_name_for_locale_with_env(
+    handler: &ProtocolHandler,
+    locale: Option<&str>,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<Option<String>, LinuxError> {
+    let path: PathBuf = get_path(&handler.name, config, env)?;
+    let file: File = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(io_error(&path)(err)),
+    };
+    let de: DesktopEntry = DesktopEntry::from_reader(BufReader::new(file))?;
+
+    Ok(de.localized_name(locale).map(|x| x.to_string()))
+}
+
+fn points_at(mimeapps: &MimeApps, handler: &ProtocolHandler) -> bool {
+    mimeapps.get_default(&scheme_mime_type(&handler.protocol_name))
+        == Some(&format!("{}.desktop", handler.name))
+}
+
+pub fn is_default(handler: &ProtocolHandler) -> Result<bool, LinuxError> {
+    let mimeapps: MimeApps = MimeApps::load(&get_mimeapps_path(&ProcessEnvironment)?)?;
+
+    Ok(points_at(&mimeapps, handler))
+}
+
+fn default_handler_in(paths: &[PathBuf], scheme: &str) -> Result<Option<String>, LinuxError> {
+    let mime_type: String = scheme_mime_type(scheme);
+    for path in paths {
+        let mimeapps: MimeApps = MimeApps::load(path)?;
+        if let Some(desktop_id) = mimeapps.get_default(&mime_type) {
+            return Ok(Some(desktop_id.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+pub fn default_handler_for(scheme: &str) -> Result<Option<String>, LinuxError> {
+    default_handler_in(&get_mimeapps_search_paths(&ProcessEnvironment)?, scheme)
+}
+
+pub fn default_handler_name(scheme: &str) -> Result<Option<String>, LinuxError> {
+    default_handler_name_with_env(scheme, &ProcessEnvironment)
+}
+
+fn default_handler_name_with_env(
+    scheme: &str,
+    env: &dyn Environment,
+) -> Result<Option<String>, LinuxError> {
+    let Some(desktop_id) = default_handler_in(&get_mimeapps_search_paths(env)?, scheme)? else {
+        return Ok(None);
+    };
+    for dir in get_applications_search_dirs(env)? {
+        let path: PathBuf = dir.join(&desktop_id);
+        let file: File = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(io_error(&path)(err)),
+        };
+        let de: DesktopEntry = DesktopEntry::from_reader(BufReader::new(file))?;
+        return Ok(de
+            .localized_name(env.messages_locale().as_deref())
+            .map(|x| x.to_string()));
+    }
+    Ok(None)
+}
+
+fn set_default_handler_at(path: &Path, scheme: &str, desktop_id: &str) -> Result<(), LinuxError> {
+    let mut mimeapps: MimeApps = MimeApps::load(path)?;
+    mimeapps.set_default(&scheme_mime_type(scheme), desktop_id);
+    mimeapps.save(path)
+}
+
+pub fn set_default_handler(scheme: &str, desktop_id: &str) -> Result<(), LinuxError> {
+    set_default_handler_at(&get_mimeapps_path(&ProcessEnvironment)?, scheme, desktop_id)
+}
+
+fn reset_to_default_at(
+    path: &Path,
+    scheme: &str,
+    previous: Option<String>,
+) -> Result<(), LinuxError> {
+    match previous {
+        Some(desktop_id) => set_default_handler_at(path, scheme, &desktop_id),
+        None => {
+            let mut mimeapps: MimeApps = MimeApps::load(path)?;
+            mimeapps.clear_default(&scheme_mime_type(scheme));
+            mimeapps.save(path)
+        }
+    }
+}
+
+pub fn reset_to_default(scheme: &str, previous: Option<String>) -> Result<(), LinuxError> {
+    reset_to_default_at(&get_mimeapps_path(&ProcessEnvironment)?, scheme, previous)
+}
+
+fn status_at(
+    path: &Path,
+    mimeapps_path: &Path,
+    handler: &ProtocolHandler,
+) -> Result<HandlerStatus, LinuxError> {
+    let is_default: bool = points_at(&MimeApps::load(mimeapps_path)?, handler);
+
+    if !path.exists() {
+        return Ok(HandlerStatus {
+            path: path.to_path_buf(),
+            exists: false,
+            is_registered: false,
+            is_default,
+            is_exec_valid: false,
+        });
+    }
+
+    let de: DesktopEntry =
+        DesktopEntry::from_reader(BufReader::new(File::open(path).map_err(io_error(path))?))?;
+    Ok(HandlerStatus {
+        path: path.to_path_buf(),
+        exists: true,
+        is_registered: de.has_scheme_handler(&handler.protocol_name) && !de.is_hidden(),
+        is_default,
+        is_exec_valid: de.is_exec_valid()
+            && de
+                .check_exec_prefixes(&handler.allowed_exec_prefixes)
+                .is_ok(),
+    })
+}
+
+pub fn status(handler: &ProtocolHandler) -> Result<HandlerStatus, LinuxError> {
+    status_with_config(handler, &RegistrationConfig::default())
+}
+
+pub fn status_with_config(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+) -> Result<HandlerStatus, LinuxError> {
+    status_with_env(handler, config, &ProcessEnvironment)
+}
+
+fn status_with_env(
+    handler: &ProtocolHandler,
+    config: &RegistrationConfig,
+    env: &dyn Environment,
+) -> Result<HandlerStatus, LinuxError> {
+    status_at(
+        &get_path(&handler.name, config, env)?,
+        &get_mimeapps_path(env)?,
+        handler,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UrlArity;
+    use indexmap::indexmap;
+    use std::{fs, io::BufReader};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_invalid_entry() {
+        let content: String = "[Not Desktop Entry]".to_string();
+        assert!(
+            DesktopEntry::try_from(content).is_err(),
+            "Not a desktop entry"
+        )
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let content: String = "[Desktop Entry]\nnotvalid".to_string();
+        assert!(
+            DesktopEntry::try_from(content).is_err(),
+            "Invalid field format"
+        )
+    }
+
+    #[test]
+    fn test_valid() {
+        let content: String = "[Desktop Entry]\nfield1=val1\nfield2=val2".to_string();
+        let de = DesktopEntry::try_from(content);
+        assert!(de.is_ok());
+
+        let de = de.unwrap();
+        assert!(de.data.contains_key("field1"));
+        assert!(de.data.contains_key("field2"));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let content: &[u8] = b"[Desktop Entry]\nfield1=val1\nMimeType=x-scheme-handler/app";
+        let de = DesktopEntry::from_reader(BufReader::new(content)).unwrap();
+        assert_eq!(de.data.get("field1"), Some(&"val1".to_string()));
+        assert!(de.contains_mime_type("x-scheme-handler/app"));
+        assert!(!de.data.contains_key("Exec"));
+    }
+
+    #[test]
+    fn test_from_reader_invalid() {
+        let content: &[u8] = b"[Desktop Entry]\nnotvalid";
+        assert!(DesktopEntry::from_reader(BufReader::new(content)).is_err());
+    }
+
+    #[test]
+    fn test_comments_round_trip() {
+        let content: String =
+            "[Desktop Entry]\nName=app\n\n# my custom note\nExec=app %u\n# trailing note"
+                .to_string();
+        let de = DesktopEntry::try_from(content.clone()).unwrap();
+        assert_eq!(de.to_string(), content);
+    }
+
+    #[test]
+    fn test_comments_preserved_after_edit() {
+        let content: String =
+            "[Desktop Entry]\nName=app\n# my custom note\nExec=app %u".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_scheme_handler("x-scheme-handler/app".to_string());
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nName=app\n# my custom note\nExec=app %u\nMimeType=x-scheme-handler/app"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let de: DesktopEntry = "[Desktop Entry]\nfield1=val1\nfield2=val2".parse().unwrap();
+        assert_eq!(de.data.get("field1"), Some(&"val1".to_string()));
+        assert!(!de.data.contains_key("Exec"));
+
+        assert!("[Not Desktop Entry]".parse::<DesktopEntry>().is_err());
+    }
+
+    #[test]
+    fn test_group_names() {
+        let content: String = "[Desktop Entry]\nName=app\nActions=new;private;\n\n[Desktop Action new]\nName=New Window\nExec=app --new\n\n[Desktop Action private]\nName=Private Window\nExec=app --private".to_string();
+        let de = DesktopEntry::try_from(content.clone()).unwrap();
+        assert_eq!(
+            de.group_names(),
+            vec![
+                "Desktop Entry",
+                "Desktop Action new",
+                "Desktop Action private"
+            ]
+        );
+        assert_eq!(de.data.get("Name"), Some(&"app".to_string()));
+        assert_eq!(de.to_string(), content);
+    }
+
+    #[test]
+    fn test_equivalent_to() {
+        let a = DesktopEntry::try_from(
+            "[Desktop Entry]\nName=app\nMimeType=text/html;x-scheme-handler/myapp;\n[Desktop Action new]\nExec=app --new"
+                .to_string(),
+        )
+        .unwrap();
+        let b = DesktopEntry::try_from(
+            "[Desktop Entry]\n# reordered\nMimeType=x-scheme-handler/myapp;text/html;text/html\nName=app\n[Desktop Action new]\n\nExec=app --new"
+                .to_string(),
+        )
+        .unwrap();
+        assert!(a.equivalent_to(&b));
+        assert!(b.equivalent_to(&a));
+
+        let c = DesktopEntry::try_from(
+            "[Desktop Entry]\nName=app\nMimeType=text/html;\n[Desktop Action new]\nExec=app --new"
+                .to_string(),
+        )
+        .unwrap();
+        assert!(!a.equivalent_to(&c));
+        let d = DesktopEntry::try_from(
+            "[Desktop Entry]\nName=app\nMimeType=text/html;x-scheme-handler/myapp;".to_string(),
+        )
+        .unwrap();
+        assert!(!a.equivalent_to(&d));
+    }
+
+    #[test]
+    fn test_handler_equivalent_to() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let on_disk = DesktopEntry::try_from(
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp;text/html;\nExec=/opt/app/bin/app %u"
+                .to_string(),
+        )
+        .unwrap();
+        assert!(equivalent_to_with_env(&handler, &on_disk, &env).unwrap());
+
+        let handler = ProtocolHandler::new("myapp", "other");
+        assert!(!equivalent_to_with_env(&handler, &on_disk, &env).unwrap());
+        let handler = ProtocolHandler::new("myapp", "myapp").generic_name("Browser");
+        assert!(!equivalent_to_with_env(&handler, &on_disk, &env).unwrap());
+    }
+
+    #[test]
+    fn test_minimal_entry_is_valid() {
+        let dir = tempdir().unwrap();
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let report =
+            register_with_env(&ProtocolHandler::new("myapp", "myapp"), &config, &env).unwrap();
+        let content: String = fs::read_to_string(report.path).unwrap();
+        assert_eq!(
+            content,
+            "[Desktop Entry]\nType=Application\nName=myapp\nExec=/opt/app/bin/app %u\nMimeType=x-scheme-handler/myapp\nX-ProtocolHandler-Managed=true"
+        );
+        DesktopEntry::try_from(content).unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let validate = |content: &str| {
+            DesktopEntry::try_from(content.to_string())
+                .unwrap()
+                .validate()
+        };
+        assert!(validate("[Desktop Entry]\nType=Application\nName=app\nExec=app").is_ok());
+        assert!(validate("[Desktop Entry]\nType=Link\nName=app\nURL=https://example.com").is_ok());
+        assert!(validate(
+            "[Desktop Entry]\nType=Application\nName=app\nDBusActivatable=true\nMimeType=x-scheme-handler/myapp"
+        )
+        .is_ok());
+
+        for content in [
+            "[Desktop Entry]\nName=app\nExec=app",
+            "[Desktop Entry]\nType=Application\nExec=app",
+            "[Desktop Entry]\nType=Application\nName=app",
+            "[Desktop Entry]\nType=Link\nName=app",
+            "[Desktop Entry]\nType=Service\nName=app",
+            "[Desktop Entry]\nType=Application\nName=app\nExec=app\nHidden=yes",
+            "[Desktop Entry]\nType=Application\nName=app\nExec=app\nMimeType=x-scheme-handler/myapp",
+        ] {
+            assert!(
+                matches!(validate(content), Err(LinuxError::ValidationError(_))),
+                "{content:?}"
+            );
+        }
+        assert!(matches!(
+            validate("[Desktop Entry]\nType=Application\nName=app\nExec=app\nBad Key=1"),
+            Err(LinuxError::KeyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_contains_key_and_len() {
+        let de = DesktopEntry::default();
+        assert!(de.is_empty());
+        assert_eq!(de.len(), 0);
+        assert!(!de.contains_key("Name"));
+
+        let de = DesktopEntry::try_from(
+            "[Desktop Entry]\n# comment\nName=app\nName[de]=App\n[Desktop Action new]\nExec=app"
+                .to_string(),
+        )
+        .unwrap();
+        assert!(!de.is_empty());
+        assert_eq!(de.len(), 2);
+        assert!(de.contains_key("Name"));
+        assert!(de.contains_key("Name[de]"));
+        assert!(!de.contains_key("Exec"));
+    }
+
+    #[test]
+    fn test_mime_type() {
+        let mut de = DesktopEntry::try_from("[Desktop Entry]\nName=app".to_string()).unwrap();
+        assert_eq!(de.mime_type(), None);
+
+        de.set_mime_type("text/html;x-scheme-handler/myapp;");
+        assert_eq!(de.mime_type(), Some("text/html;x-scheme-handler/myapp;"));
+        assert!(de.has_scheme_handler("myapp"));
+
+        let de = DesktopEntry::try_from(de.to_string()).unwrap();
+        assert_eq!(de.mime_type(), Some("text/html;x-scheme-handler/myapp;"));
+    }
+
+    #[test]
+    fn test_write_to() {
+        let content: String =
+            "\u{feff}[Desktop Entry]\n# comment\nName=app\n[Desktop Action new]\nName=New"
+                .to_string();
+        let de = DesktopEntry::try_from(content).unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        de.write_to(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), de.to_string());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let content: String =
+            "[Desktop Entry]\nExec=app \"C:\\\\\\\\dir\" %u\nComment=a\\nb\\s\nName=\tapp"
+                .to_string();
+        let de = DesktopEntry::try_from(content.clone()).unwrap();
+        assert_eq!(de.serialize().unwrap(), content);
+        assert_eq!(
+            split_exec(&unescape_value(&de.data["Exec"])).unwrap(),
+            vec!["app", "C:\\dir", "%u"]
+        );
+
+        let mut de = DesktopEntry::default();
+        let handler = ProtocolHandler::new("myapp", "myapp").wrapper_command("echo \"it's\"");
+        prepare_entry(&mut de, &handler, &FakeEnvironment::new(&[])).unwrap();
+        assert_eq!(de.serialize().unwrap(), de.to_string());
+
+        let mut de = DesktopEntry::default();
+        de.data
+            .insert("Comment".to_string(), "two\nlines\r".to_string());
+        assert_eq!(
+            de.serialize().unwrap(),
+            "[Desktop Entry]\nComment=two\\nlines\\r"
+        );
+        assert_eq!(de.to_string(), "[Desktop Entry]\nComment=two\nlines\r");
+
+        de.data.insert("Name".to_string(), "a\0b".to_string());
+        assert!(matches!(
+            de.serialize(),
+            Err(LinuxError::ValidationError(_))
+        ));
+
+        de.data.shift_remove("Name");
+        de.data.insert("Not A Key".to_string(), "value".to_string());
+        assert!(matches!(
+            de.serialize(),
+            Err(LinuxError::KeyError(key)) if key == "Not A Key"
+        ));
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let de = DesktopEntry::try_from(
+            "[Desktop Entry]\nType=Application\n# comment\nName=app\n[Desktop Action new]\nName=New"
+                .to_string(),
+        )
+        .unwrap();
+        let mut pairs: Vec<(&str, &str)> = Vec::new();
+        for (key, value) in &de {
+            pairs.push((key, value));
+        }
+        assert_eq!(pairs, vec![("Type", "Application"), ("Name", "app")]);
+    }
+
+    #[test]
+    fn test_main_group_not_first() {
+        let content: String = "[Desktop Action new]\nName=New Window\nExec=app --new\n[Desktop Entry]\nName=app\nActions=new;".to_string();
+        let de = DesktopEntry::try_from(content).unwrap();
+        assert_eq!(
+            de.group_names(),
+            vec!["Desktop Entry", "Desktop Action new"]
+        );
+        assert_eq!(de.data.get("Name"), Some(&"app".to_string()));
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nName=app\nActions=new;\n[Desktop Action new]\nName=New Window\nExec=app --new"
+        );
+
+        let content: String =
+            "[Desktop Action new]\nName=New Window\n[Desktop Entry]\nName=app\n[Desktop Entry]"
+                .to_string();
+        assert!(DesktopEntry::try_from(content).is_err());
+
+        let content: String = "[Desktop Action new]\nName=New Window".to_string();
+        assert!(DesktopEntry::try_from(content).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_group() {
+        let content: String =
+            "[Desktop Entry]\nName=app\n[Desktop Action new]\n[Desktop Action new]".to_string();
+        assert!(DesktopEntry::try_from(content).is_err());
+
+        let content: String = "[Desktop Entry]\nName=app\n[Desktop Entry]".to_string();
+        assert!(DesktopEntry::try_from(content).is_err());
+    }
+
+    #[test]
+    fn test_to_string() {
+        let de: DesktopEntry = DesktopEntry {
+            data: indexmap! {
+                "field1".to_string() => "val1".to_string(),
+                "field2".to_string() => "val2".to_string(),
+            },
+            ..Default::default()
+        };
+        assert_eq!(de.to_string(), "[Desktop Entry]\nfield1=val1\nfield2=val2")
+    }
+
+    #[test]
+    fn test_display() {
+        let de: DesktopEntry = DesktopEntry {
+            data: indexmap! {
+                "field1".to_string() => "val1".to_string(),
+                "field2".to_string() => "val2".to_string(),
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            format!("{}", de),
+            "[Desktop Entry]\nfield1=val1\nfield2=val2"
+        );
+        assert_eq!(format!("{}", de), de.to_string());
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1".as_bytes()).unwrap();
+        let other = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1".as_bytes()).unwrap();
+        assert!(de.diff(&other).is_empty());
+    }
+
+    #[test]
+    fn test_diff_added() {
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1".as_bytes()).unwrap();
+        let other =
+            DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1\nfield2=val2".as_bytes())
+                .unwrap();
+        assert_eq!(
+            de.diff(&other),
+            vec![Change::Added("field2".to_string(), "val2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_diff_removed() {
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1\nfield2=val2".as_bytes())
+            .unwrap();
+        let other = DesktopEntry::from_reader("[Desktop Entry]\nfield2=val2".as_bytes()).unwrap();
+        assert_eq!(
+            de.diff(&other),
+            vec![Change::Removed("field1".to_string(), "val1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_diff_modified() {
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val1".as_bytes()).unwrap();
+        let other = DesktopEntry::from_reader("[Desktop Entry]\nfield1=val2".as_bytes()).unwrap();
+        assert_eq!(
+            de.diff(&other),
+            vec![Change::Modified(
+                "field1".to_string(),
+                "val1".to_string(),
+                "val2".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_insert_scheme_handler() {
         let content: String = "[Desktop Entry]\nfield1=val1\nfield2=val2".to_string();
         let mut de = DesktopEntry::try_from(content).unwrap();
         de.insert_scheme_handler("x-scheme-handler/app".to_string());
         assert_eq!(
-            de.data.get("MimeType"),
-            Some(&"x-scheme-handler/app".to_string())
+            de.data.get("MimeType"),
+            Some(&"x-scheme-handler/app".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_scheme_handler_has_mime_type() {
+        let content: String =
+            "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=application/cdf".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_scheme_handler("x-scheme-handler/app".to_string());
+        assert_eq!(
+            de.data.get("MimeType"),
+            Some(&"application/cdf;x-scheme-handler/app".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_scheme_handler_replace() {
+        let content: String =
+            "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=x-scheme-handler/App".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_scheme_handler("x-scheme-handler/app".to_string());
+        assert_eq!(
+            de.data.get("MimeType"),
+            Some(&"x-scheme-handler/app".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_scheme_handler_second_scheme() {
+        let content: String =
+            "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=x-scheme-handler/app".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_scheme_handler("x-scheme-handler/app2".to_string());
+        assert_eq!(
+            de.data.get("MimeType"),
+            Some(&"x-scheme-handler/app;x-scheme-handler/app2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_delete_scheme_handler_exact() {
+        let content: String =
+            "[Desktop Entry]\nMimeType=x-scheme-handler/app2;x-scheme-handler/app;x-scheme-handler/app-extra".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.delete_scheme_handler("x-scheme-handler/app");
+        assert_eq!(
+            de.data.get("MimeType"),
+            Some(&"x-scheme-handler/app2;x-scheme-handler/app-extra".to_string())
+        );
+        assert!(!de.contains_mime_type("x-scheme-handler/app"));
+    }
+
+    #[test]
+    fn test_scheme_mime_type_lowercase() {
+        assert_eq!(scheme_mime_type("MyApp"), "x-scheme-handler/myapp");
+    }
+
+    #[test]
+    fn test_register_mixed_case_query_lowercase() {
+        let content: String = "[Desktop Entry]\nfield1=val1".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_scheme_handler(scheme_mime_type("MyApp"));
+        assert_eq!(
+            de.data.get("MimeType"),
+            Some(&"x-scheme-handler/myapp".to_string())
+        );
+        assert!(de.contains_mime_type(&scheme_mime_type("myapp")));
+    }
+
+    #[test]
+    fn test_query_legacy_mixed_case_entry() {
+        let content: String = "[Desktop Entry]\nMimeType=x-scheme-handler/MyApp".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        assert!(de.contains_mime_type(&scheme_mime_type("myapp")));
+
+        de.delete_scheme_handler(&scheme_mime_type("MYAPP"));
+        assert!(!de.data.contains_key("MimeType"));
+    }
+
+    #[test]
+    fn test_has_scheme_handler() {
+        let de: DesktopEntry =
+            "[Desktop Entry]\nMimeType=text/plain;x-scheme-handler/app-extra;x-scheme-handler/App"
+                .parse()
+                .unwrap();
+        assert!(de.has_scheme_handler("app"));
+        assert!(de.has_scheme_handler("app-extra"));
+        assert!(!de.has_scheme_handler("ap"));
+        assert!(!de.has_scheme_handler("app-"));
+        assert!(!de.has_scheme_handler("text"));
+        assert!(!de.has_scheme_handler("other"));
+
+        let de: DesktopEntry = "[Desktop Entry]\nName=app".parse().unwrap();
+        assert!(!de.has_scheme_handler("app"));
+    }
+
+    #[test]
+    fn test_insert_working_dir() {
+        let content: String = "[Desktop Entry]\nExec=app %u".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_working_dir(Path::new("/opt/app"));
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nExec=app %u\nPath=/opt/app"
+        );
+    }
+
+    #[test]
+    fn test_insert_working_dir_preserves_existing() {
+        let content: String = "[Desktop Entry]\nExec=app %u\nPath=/srv/app".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        de.insert_working_dir(Path::new("/opt/app"));
+        assert_eq!(de.data.get("Path"), Some(&"/srv/app".to_string()));
+    }
+
+    #[test]
+    fn test_register_from_template() {
+        let dir = tempdir().unwrap();
+        let template = dir.path().join("template.desktop");
+        fs::write(
+            &template,
+            "[Desktop Entry]\nType=Application\nName=My App\nX-Custom-Key=custom",
+        )
+        .unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().join("apps")),
+            run_db_update: false,
+            file_mode: Some(0o600),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let report = register_from_template_with_env(&handler, &template, &config, &env).unwrap();
+        assert_eq!(report.path, dir.path().join("apps/myapp.desktop"));
+        assert!(report.created);
+        let de = DesktopEntry::try_from(fs::read_to_string(&report.path).unwrap()).unwrap();
+        assert_eq!(de.data.get("Type"), Some(&"Application".to_string()));
+        assert_eq!(de.data.get("Name"), Some(&"My App".to_string()));
+        assert_eq!(de.data.get("X-Custom-Key"), Some(&"custom".to_string()));
+        assert!(de.data.get("Exec").unwrap().ends_with(" %u"));
+        assert!(de.contains_mime_type("x-scheme-handler/myapp"));
+        assert_eq!(
+            fs::metadata(&report.path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn test_register_from_template_keeps_exec() {
+        let dir = tempdir().unwrap();
+        let template = dir.path().join("template.desktop");
+        fs::write(&template, "[Desktop Entry]\nExec=/opt/app/run %u").unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            run_db_update: false,
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let report = register_from_template_with_env(&handler, &template, &config, &env).unwrap();
+        let de = DesktopEntry::try_from(fs::read_to_string(&report.path).unwrap()).unwrap();
+        assert_eq!(de.data.get("Exec"), Some(&"/opt/app/run %u".to_string()));
+    }
+
+    #[test]
+    fn test_register_from_template_failure_removes_new_file() {
+        let dir = tempdir().unwrap();
+        let template = dir.path().join("template.desktop");
+        fs::write(&template, "[Desktop Entry]\nName=App").unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().join("apps")),
+            run_db_update: false,
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("myapp", "myapp").extra_key("Not A Key", "value");
+        assert!(matches!(
+            register_from_template_with_env(&handler, &template, &config, &env),
+            Err(LinuxError::KeyError(_))
+        ));
+        assert!(!dir.path().join("apps/myapp.desktop").exists());
+    }
+
+    #[test]
+    fn test_is_exec_valid() {
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nExec=/bin/sh %u".as_bytes()).unwrap();
+        assert!(de.is_exec_valid());
+
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nExec=sh %u".as_bytes()).unwrap();
+        assert!(de.is_exec_valid());
+
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nExec=/nonexistent/app %u".as_bytes())
+            .unwrap();
+        assert!(!de.is_exec_valid());
+
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nName=app".as_bytes()).unwrap();
+        assert!(!de.is_exec_valid());
+    }
+
+    #[test]
+    fn test_status_missing_file() {
+        let dir = tempdir().unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        };
+        let path = dir.path().join("myapp.desktop");
+        let status = status_at(&path, &dir.path().join("mimeapps.list"), &handler).unwrap();
+        assert_eq!(
+            status,
+            HandlerStatus {
+                path,
+                exists: false,
+                is_registered: false,
+                is_default: false,
+                is_exec_valid: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_status_registered_not_default() {
+        let dir = tempdir().unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        };
+        let path = dir.path().join("myapp.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]\nExec=/bin/sh %u\nMimeType=x-scheme-handler/myapp",
+        )
+        .unwrap();
+        let mimeapps_path = dir.path().join("mimeapps.list");
+        fs::write(
+            &mimeapps_path,
+            "[Default Applications]\nx-scheme-handler/myapp=other.desktop",
+        )
+        .unwrap();
+
+        let status = status_at(&path, &mimeapps_path, &handler).unwrap();
+        assert!(status.exists);
+        assert!(status.is_registered);
+        assert!(!status.is_default);
+        assert!(status.is_exec_valid);
+    }
+
+    #[test]
+    fn test_status_default_with_invalid_exec() {
+        let dir = tempdir().unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        };
+        let path = dir.path().join("myapp.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]\nExec=/nonexistent/app %u\nMimeType=x-scheme-handler/myapp",
+        )
+        .unwrap();
+        let mimeapps_path = dir.path().join("mimeapps.list");
+        fs::write(
+            &mimeapps_path,
+            "[Default Applications]\nx-scheme-handler/myapp=myapp.desktop",
+        )
+        .unwrap();
+
+        let status = status_at(&path, &mimeapps_path, &handler).unwrap();
+        assert!(status.is_registered);
+        assert!(status.is_default);
+        assert!(!status.is_exec_valid);
+    }
+
+    #[test]
+    fn test_check_exec_prefixes_quoted_path() {
+        let dir = tempdir().unwrap();
+        let bin = dir.path().join("My App/bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("app"), "").unwrap();
+        fs::set_permissions(bin.join("app"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut de = DesktopEntry::default();
+        de.data.insert(
+            "Exec".to_string(),
+            format!("\"{}\" %u", bin.join("app").display()),
+        );
+        assert!(de.is_exec_valid());
+        assert!(de.check_exec_prefixes(&[dir.path().join("My App")]).is_ok());
+        assert!(matches!(
+            de.check_exec_prefixes(&[dir.path().join("My")]),
+            Err(LinuxError::ExecNotAllowed(_))
+        ));
+
+        de.data
+            .insert("Exec".to_string(), "\"/opt/app %u".to_string());
+        assert!(!de.is_exec_valid());
+        assert!(matches!(
+            de.check_exec_prefixes(&["/opt"]),
+            Err(LinuxError::ExecError(_))
+        ));
+    }
+
+    #[test]
+    fn test_status_allowed_exec_prefixes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]\nExec=/bin/sh %u\nMimeType=x-scheme-handler/myapp",
+        )
+        .unwrap();
+        let mimeapps_path = dir.path().join("mimeapps.list");
+        let sh = fs::canonicalize("/bin/sh").unwrap();
+
+        let handler =
+            ProtocolHandler::new("myapp", "myapp").allowed_exec_prefixes(&[sh.parent().unwrap()]);
+        assert!(
+            status_at(&path, &mimeapps_path, &handler)
+                .unwrap()
+                .is_exec_valid
+        );
+
+        let handler = ProtocolHandler::new("myapp", "myapp").allowed_exec_prefixes(&["/opt/app"]);
+        let status = status_at(&path, &mimeapps_path, &handler).unwrap();
+        assert!(status.is_registered);
+        assert!(!status.is_exec_valid);
+    }
+
+    #[test]
+    fn test_prepare_entry_default() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        };
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert!(de.data.get("Exec").unwrap().ends_with(" %u"));
+        assert!(!de.data.contains_key("DBusActivatable"));
+    }
+
+    #[test]
+    fn test_prepare_entry_dbus_activatable() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        }
+        .dbus_activatable(true);
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert!(!de.data.get("Exec").unwrap().contains('%'));
+        assert_eq!(de.data.get("DBusActivatable"), Some(&"true".to_string()));
+        assert!(de.contains_mime_type("x-scheme-handler/myapp"));
+    }
+
+    #[test]
+    fn test_prepare_entry_spec_version() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        }
+        .spec_version("1.5");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert!(de.to_string().starts_with("[Desktop Entry]\nVersion=1.5\n"));
+
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]\nName=app".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert!(!de.data.contains_key("Version"));
+    }
+
+    #[test]
+    fn test_prepare_entry_invalid_spec_version() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        }
+        .spec_version("2.0");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &HostEnvironment),
+            Err(LinuxError::VersionError(version)) if version == "2.0"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_exe() {
+        assert_eq!(
+            resolve_exe(PathBuf::from("/proc/self/exe")).unwrap(),
+            env::current_exe().unwrap().canonicalize().unwrap()
+        );
+        assert!(matches!(
+            resolve_exe(PathBuf::from("/usr/bin/app (deleted)")),
+            Err(LinuxError::ExecError(_))
+        ));
+        assert!(matches!(
+            resolve_exe(PathBuf::from("/proc/0/exe")),
+            Err(LinuxError::ExecError(_))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_entry_flatpak() {
+        let env = FakeEnvironment::new(&[("FLATPAK_ID", "org.example.App")]);
+        let handler = ProtocolHandler::new("org.example.App", "example");
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"flatpak run org.example.App %u".to_string())
+        );
+
+        let env = FakeEnvironment::new(&[]);
+        let mut de: DesktopEntry =
+            "[Desktop Entry]\nX-Flatpak=org.example.Other\nX-Flatpak-RenamedFrom=other.desktop;"
+                .parse()
+                .unwrap();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"flatpak run org.example.Other %u".to_string())
+        );
+    }
+
+    #[test]
+    fn test_flatpak_keys_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("org.example.App.desktop");
+        let content: &str = "[Desktop Entry]\nExec=/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=app org.example.App @@u %u @@\nX-Flatpak=org.example.App\nX-Flatpak-RenamedFrom=app.desktop;\nMimeType=text/plain";
+        fs::write(&path, content).unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("org.example.App", "example");
+
+        register_with_env(&handler, &config, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{content};x-scheme-handler/example")
+        );
+
+        unregister_with_env(&handler, &config, &env).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_prepare_entry_generic_name() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp")
+            .generic_name("Web Browser")
+            .localized_generic_name("de", "Webbrowser");
+        let mut de: DesktopEntry = "[Desktop Entry]\nExec=app %u".parse().unwrap();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/myapp\nGenericName=Web Browser\nGenericName[de]=Webbrowser"
+        );
+
+        let mut de: DesktopEntry = "[Desktop Entry]\nExec=app %u\nGenericName=Browser"
+            .parse()
+            .unwrap();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(de.data.get("GenericName"), Some(&"Browser".to_string()));
+
+        let handler = ProtocolHandler::new("myapp", "myapp").localized_generic_name("d e", "x");
+        let mut de = DesktopEntry::default();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::KeyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_entry_container() {
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let mut env = FakeEnvironment::new(&[]);
+        env.paths.push(PathBuf::from("/.dockerenv"));
+        let mut de = DesktopEntry::default();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::ContainerError)
+        ));
+
+        let env = FakeEnvironment::new(&[("container", "podman")]);
+        let mut de = DesktopEntry::default();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::ContainerError)
+        ));
+
+        let handler = ProtocolHandler::new("myapp", "myapp").host_exe("/usr/bin/myapp");
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(de.data.get("Exec"), Some(&"/usr/bin/myapp %u".to_string()));
+    }
+
+    #[test]
+    fn test_prepare_entry_snap() {
+        let exec = |handler: &ProtocolHandler, env: &FakeEnvironment| {
+            let mut de = DesktopEntry::default();
+            prepare_entry(&mut de, handler, env).unwrap();
+            de.data.get("Exec").cloned().unwrap()
+        };
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let env = FakeEnvironment::new(&[]);
+        assert_eq!(exec(&handler, &env), "/opt/app/bin/app %u");
+        assert_eq!(
+            exec(
+                &ProtocolHandler::new("myapp", "myapp").snap_name("my-snap"),
+                &env
+            ),
+            "/snap/bin/my-snap %u"
+        );
+
+        let env = FakeEnvironment::new(&[("SNAP", "/snap/my-snap/42"), ("SNAP_NAME", "my-snap")]);
+        assert_eq!(exec(&handler, &env), "/snap/bin/my-snap %u");
+        assert_eq!(
+            exec(
+                &ProtocolHandler::new("myapp", "myapp").host_exe("/usr/bin/myapp"),
+                &env
+            ),
+            "/usr/bin/myapp %u"
+        );
+    }
+
+    #[test]
+    fn test_prepare_entry_dev_binary() {
+        let exec = |handler: ProtocolHandler| {
+            let mut env = FakeEnvironment::new(&[("SNAP", "/snap/app/1"), ("SNAP_NAME", "app")]);
+            env.paths.push(PathBuf::from("/.dockerenv"));
+            let mut de = DesktopEntry::default();
+            prepare_entry(&mut de, &handler, &env).unwrap();
+            de.data.get("Exec").cloned().unwrap()
+        };
+        let handler = || ProtocolHandler::new("myapp", "myapp");
+
+        assert_eq!(
+            exec(handler().dev_binary("target/debug/app")),
+            "/home/dev/project/target/debug/app %u"
+        );
+        assert_eq!(
+            exec(handler().dev_binary("./target/debug/app")),
+            "/home/dev/project/target/debug/app %u"
+        );
+        assert_eq!(
+            exec(
+                handler()
+                    .dev_binary("/src/app/target/release/app")
+                    .host_exe("/usr/bin/app")
+            ),
+            "/src/app/target/release/app %u"
+        );
+    }
+
+    #[test]
+    fn test_prepare_entry_url_arity() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"/opt/app/bin/app %u".to_string())
+        );
+
+        let handler = ProtocolHandler::new("myapp", "myapp").url_arity(UrlArity::Multiple);
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"/opt/app/bin/app %U".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prepare_entry_command_template() {
+        let env = FakeEnvironment::new(&[]);
+        let handler =
+            ProtocolHandler::new("myapp", "myapp").command_template("/opt/app/run --open %1");
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"/opt/app/run --open %u".to_string())
+        );
+
+        let handler = ProtocolHandler::new("myapp", "myapp")
+            .url_arity(UrlArity::Multiple)
+            .command_template("/opt/app/run %1");
+        let mut de = DesktopEntry::default();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(de.data.get("Exec"), Some(&"/opt/app/run %U".to_string()));
+
+        for template in ["/opt/app/run", "/opt/app/run %1 %1"] {
+            let handler = ProtocolHandler::new("myapp", "myapp").command_template(template);
+            let mut de = DesktopEntry::default();
+            assert!(matches!(
+                prepare_entry(&mut de, &handler, &env),
+                Err(LinuxError::ExecError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_prepare_entry_keeps_user_exec() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        for exec in ["app %U", "app %F", "app"] {
+            let mut de = DesktopEntry::try_from(format!("[Desktop Entry]\nExec={exec}")).unwrap();
+            prepare_entry(&mut de, &handler, &env).unwrap();
+            assert_eq!(de.data.get("Exec"), Some(&exec.to_string()));
+            assert!(de.has_scheme_handler("myapp"));
+        }
+    }
+
+    #[test]
+    fn test_prepare_entry_url_arity_mismatch() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp")
+            .url_arity(UrlArity::Multiple)
+            .extra_key("Exec", "/opt/app/run %u");
+        let mut de = DesktopEntry::default();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::ExecError(_))
+        ));
+
+        let handler =
+            ProtocolHandler::new("myapp", "myapp").extra_key("Exec", "/opt/app/run %u %u");
+        let mut de = DesktopEntry::default();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::ExecError(_))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_entry_no_url_arg() {
+        let handler = ProtocolHandler::new("myapp", "myapp").no_url_arg();
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(
+                &resolve_exe(env::current_exe().unwrap())
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            )
+        );
+        assert!(de.contains_mime_type("x-scheme-handler/myapp"));
+    }
+
+    #[test]
+    fn test_prepare_entry_no_url_arg_conflict() {
+        let handler = ProtocolHandler::new("myapp", "myapp")
+            .no_url_arg()
+            .extra_key("Exec", "/opt/app/run %u");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &HostEnvironment),
+            Err(LinuxError::ExecError(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("X-GNOME-UsesNotifications"));
+        assert!(is_valid_key("Name[de_DE.UTF-8@euro]"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("Bad Key"));
+        assert!(!is_valid_key("Bad=Key"));
+        assert!(!is_valid_key("Name[de"));
+        assert!(!is_valid_key("Name[]"));
+    }
+
+    #[test]
+    fn test_prepare_entry_extra_keys() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        }
+        .extra_key("X-GNOME-UsesNotifications", "true")
+        .extra_key("X-Vendor-Id", "42")
+        .extra_key("Exec", "/opt/app/run %u");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &HostEnvironment).unwrap();
+        assert_eq!(
+            de.data.get("X-GNOME-UsesNotifications"),
+            Some(&"true".to_string())
+        );
+        assert_eq!(de.data.get("X-Vendor-Id"), Some(&"42".to_string()));
+        assert_eq!(de.data.get("Exec"), Some(&"/opt/app/run %u".to_string()));
+    }
+
+    #[test]
+    fn test_prepare_entry_invalid_extra_key() {
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            ..Default::default()
+        }
+        .extra_key("Not A Key", "value");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &HostEnvironment),
+            Err(LinuxError::KeyError(key)) if key == "Not A Key"
+        ));
+    }
+
+    #[test]
+    fn test_open_symlink_follow() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("dotfiles.desktop");
+        fs::write(&target, "[Desktop Entry]\nName=app").unwrap();
+        let link = dir.path().join("app.desktop");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let file = open_desktop_file(&link, SymlinkPolicy::Follow, true).unwrap();
+        let de = DesktopEntry::from_reader("[Desktop Entry]\nName=new".as_bytes()).unwrap();
+        write_entry(&link, &file, &de, Durability::Sync).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "[Desktop Entry]\nName=new"
+        );
+    }
+
+    #[test]
+    fn test_open_symlink_error() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("dotfiles.desktop");
+        fs::write(&target, "[Desktop Entry]\nName=app").unwrap();
+        let link = dir.path().join("app.desktop");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert!(matches!(
+            open_desktop_file(&link, SymlinkPolicy::Error, true),
+            Err(LinuxError::SymlinkError(path)) if path == link
+        ));
+        assert!(open_desktop_file(&target, SymlinkPolicy::Error, true).is_ok());
+    }
+
+    /// The real environment, minus container detection, so tests behave the
+    /// same inside and outside of containers.
+    struct HostEnvironment;
+
+    impl Environment for HostEnvironment {
+        fn var(&self, key: &str) -> Result<String, env::VarError> {
+            ProcessEnvironment.var(key)
+        }
+
+        fn current_exe(&self) -> Result<PathBuf, LinuxError> {
+            ProcessEnvironment.current_exe()
+        }
+
+        fn current_dir(&self) -> Result<PathBuf, LinuxError> {
+            ProcessEnvironment.current_dir()
+        }
+
+        fn euid(&self) -> u32 {
+            ProcessEnvironment.euid()
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            ProcessEnvironment.path_exists(path)
+        }
+
+        fn is_container(&self) -> bool {
+            false
+        }
+    }
+
+    pub(super) struct FakeEnvironment {
+        vars: IndexMap<String, String>,
+        exe: PathBuf,
+        cwd: PathBuf,
+        euid: u32,
+        paths: Vec<PathBuf>,
+    }
+
+    impl FakeEnvironment {
+        pub(super) fn new(vars: &[(&str, &str)]) -> Self {
+            FakeEnvironment {
+                vars: vars
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                exe: PathBuf::from("/opt/app/bin/app"),
+                cwd: PathBuf::from("/home/dev/project"),
+                euid: 1000,
+                paths: Vec::new(),
+            }
+        }
+    }
+
+    impl Environment for FakeEnvironment {
+        fn var(&self, key: &str) -> Result<String, env::VarError> {
+            self.vars.get(key).cloned().ok_or(env::VarError::NotPresent)
+        }
+
+        fn current_exe(&self) -> Result<PathBuf, LinuxError> {
+            Ok(self.exe.clone())
+        }
+
+        fn current_dir(&self) -> Result<PathBuf, LinuxError> {
+            Ok(self.cwd.clone())
+        }
+
+        fn euid(&self) -> u32 {
+            self.euid
+        }
+
+        fn path_exists(&self, path: &Path) -> bool {
+            self.paths.iter().any(|x| x == path)
+        }
+    }
+
+    #[test]
+    fn test_applications_dir_auto_scope() {
+        let config = RegistrationConfig {
+            scope: Scope::Auto,
+            ..Default::default()
+        };
+        let mut env = FakeEnvironment::new(&[("HOME", "/home/alice")]);
+        assert_eq!(
+            get_applications_dir(&config, &env).unwrap(),
+            PathBuf::from("/home/alice/.local/share/applications")
+        );
+
+        env.euid = 0;
+        assert_eq!(
+            get_applications_dir(&config, &env).unwrap(),
+            PathBuf::from("/usr/share/applications")
+        );
+    }
+
+    #[test]
+    fn test_io_error_names_path() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("missing.desktop");
+        let err = open_desktop_file(&path, SymlinkPolicy::Follow, false).unwrap_err();
+        assert!(matches!(&err, LinuxError::Io { path: x, .. } if *x == path));
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}: ", path.display())));
+
+        fs::write(&path, "[Desktop Entry]").unwrap();
+        let file = File::open(&path).unwrap();
+        let de = DesktopEntry::default();
+        let err = write_entry(&path, &file, &de, Durability::Buffered).unwrap_err();
+        assert!(err.to_string().contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_get_file_str_name() {
+        let dir = tempdir().unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[]);
+        let name: &str = "myapp";
+        let path = get_path(name, &config, &env).unwrap();
+        open_desktop_file(&path, SymlinkPolicy::Follow, true).unwrap();
+        assert!(dir.path().join("myapp.desktop").exists());
+    }
+
+    #[test]
+    fn test_applications_dir_isolated_env() {
+        let env = FakeEnvironment::new(&[("HOME", "/home/alice")]);
+        assert_eq!(
+            get_path("myapp", &RegistrationConfig::default(), &env).unwrap(),
+            PathBuf::from("/home/alice/.local/share/applications/myapp.desktop")
+        );
+
+        let env = FakeEnvironment::new(&[("HOME", "/home/alice"), ("XDG_DATA_HOME", "/data")]);
+        assert_eq!(
+            get_path("myapp", &RegistrationConfig::default(), &env).unwrap(),
+            PathBuf::from("/data/applications/myapp.desktop")
+        );
+
+        let env = FakeEnvironment::new(&[]);
+        assert!(matches!(
+            get_applications_dir(&RegistrationConfig::default(), &env),
+            Err(LinuxError::EnvError(env::VarError::NotPresent))
+        ));
+    }
+
+    #[test]
+    fn test_mimeapps_path_isolated_env() {
+        let env = FakeEnvironment::new(&[("HOME", "/home/bob")]);
+        assert_eq!(
+            get_mimeapps_path(&env).unwrap(),
+            PathBuf::from("/home/bob/.config/mimeapps.list")
+        );
+
+        let env =
+            FakeEnvironment::new(&[("HOME", "/home/bob"), ("XDG_CONFIG_HOME", "/tmp/config")]);
+        assert_eq!(
+            get_mimeapps_path(&env).unwrap(),
+            PathBuf::from("/tmp/config/mimeapps.list")
+        );
+    }
+
+    #[test]
+    fn test_mimeapps_search_paths_isolated_env() {
+        let env = FakeEnvironment::new(&[
+            ("HOME", "/home/carol"),
+            ("XDG_CONFIG_DIRS", "/etc/a:/etc/b"),
+            ("XDG_DATA_DIRS", "/usr/share"),
+        ]);
+        assert_eq!(
+            get_mimeapps_search_paths(&env).unwrap(),
+            vec![
+                PathBuf::from("/home/carol/.config/mimeapps.list"),
+                PathBuf::from("/etc/a/mimeapps.list"),
+                PathBuf::from("/etc/b/mimeapps.list"),
+                PathBuf::from("/home/carol/.local/share/applications/mimeapps.list"),
+                PathBuf::from("/usr/share/applications/mimeapps.list"),
+                PathBuf::from(
+                    "/home/carol/.local/share/flatpak/exports/share/applications/mimeapps.list"
+                ),
+                PathBuf::from("/var/lib/flatpak/exports/share/applications/mimeapps.list"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_applications_search_dirs_flatpak_exports() {
+        let env = FakeEnvironment::new(&[
+            ("HOME", "/home/carol"),
+            ("XDG_DATA_DIRS", "/var/lib/flatpak/exports/share:/usr/share"),
+        ]);
+        assert_eq!(
+            get_applications_search_dirs(&env).unwrap(),
+            vec![
+                PathBuf::from("/home/carol/.local/share/applications"),
+                PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+                PathBuf::from("/usr/share/applications"),
+                PathBuf::from("/home/carol/.local/share/flatpak/exports/share/applications"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_registered_flatpak_exports() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let exports = dir
+            .path()
+            .join(".local/share/flatpak/exports/share/applications");
+        fs::create_dir_all(&exports).unwrap();
+        let handler = ProtocolHandler::new("org.example.App", "myapp");
+        assert!(!is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+
+        fs::write(
+            exports.join("org.example.App.desktop"),
+            "[Desktop Entry]\nExec=flatpak run org.example.App %u\nMimeType=x-scheme-handler/myapp",
+        )
+        .unwrap();
+        assert!(is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+
+        fs::write(
+            exports.join("mimeapps.list"),
+            "[Default Applications]\nx-scheme-handler/myapp=org.example.App.desktop",
+        )
+        .unwrap();
+        assert_eq!(
+            default_handler_in(&get_mimeapps_search_paths(&env).unwrap(), "myapp").unwrap(),
+            Some("org.example.App.desktop".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("myapp").is_ok());
+        assert!(validate_name("org.example.My-App_2").is_ok());
+        for name in [
+            "",
+            "../../evil",
+            "..",
+            "a/../b",
+            "sub/app",
+            "/etc/app",
+            ".hidden",
+            "my app",
+            " myapp",
+            "my\tapp",
+            "app\0",
+            "app\nHidden",
+            "app=1",
+            "app;other",
+            "app[de]",
+            "app\u{7f}",
+        ] {
+            assert!(
+                matches!(validate_name(name), Err(LinuxError::InvalidName(_))),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_register_rejects_traversal() {
+        let dir = tempdir().unwrap();
+        let applications = dir.path().join("share/applications");
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let config = RegistrationConfig {
+            applications_dir: Some(applications.clone()),
+            ..Default::default()
+        };
+        let handler = ProtocolHandler::new("../../evil", "myapp");
+        assert!(matches!(
+            register_with_env(&handler, &config, &env),
+            Err(LinuxError::InvalidName(_))
+        ));
+        assert!(!dir.path().join("evil.desktop").exists());
+        assert!(!applications.exists());
+    }
+
+    #[test]
+    fn test_register_rejects_malicious_scheme() {
+        let dir = tempdir().unwrap();
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        for protocol_name in [
+            "myapp;x-scheme-handler/https",
+            "myapp\nExec=evil",
+            "myapp=1",
+            "my app",
+            "",
+        ] {
+            let handler = ProtocolHandler::new("myapp", protocol_name);
+            assert!(
+                matches!(
+                    register_with_env(&handler, &config, &env),
+                    Err(LinuxError::SchemeError(_))
+                ),
+                "{protocol_name:?}"
+            );
+        }
+        assert!(!dir.path().join("myapp.desktop").exists());
+    }
+
+    #[test]
+    fn test_register_force() {
+        let dir = tempdir().unwrap();
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let path = dir.path().join("myapp.desktop");
+        let mut config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(matches!(
+            register_with_env(&handler, &config, &env),
+            Err(LinuxError::ParseError { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "garbage");
+
+        config.force = true;
+        config.create_backup = true;
+        register_with_env(&handler, &config, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nType=Application\nName=myapp\nExec=/opt/app/bin/app %u\nMimeType=x-scheme-handler/myapp"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("myapp.desktop.bak")).unwrap(),
+            "garbage"
+        );
+
+        fs::write(&path, b"[Desktop Entry]\nName=caf\xe9").unwrap();
+        register_with_env(&handler, &config, &env).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("MimeType=x-scheme-handler/myapp"));
+    }
+
+    #[test]
+    fn test_register_file_mode() {
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let mode = |config: RegistrationConfig| {
+            let dir = tempdir().unwrap();
+            let config = RegistrationConfig {
+                applications_dir: Some(dir.path().to_path_buf()),
+                ..config
+            };
+            let report = register_with_env(&handler, &config, &env).unwrap();
+            fs::metadata(report.path).unwrap().permissions().mode() & 0o777
+        };
+
+        assert_eq!(mode(RegistrationConfig::default()), 0o644);
+        assert_eq!(
+            mode(RegistrationConfig {
+                file_mode: Some(0o600),
+                ..Default::default()
+            }),
+            0o600
+        );
+        assert_eq!(
+            mode(RegistrationConfig {
+                scope: Scope::System,
+                file_mode: Some(0o600),
+                ..Default::default()
+            }),
+            0o644
+        );
+    }
+
+    #[test]
+    fn test_register_durability() {
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        for durability in [Durability::Sync, Durability::Buffered] {
+            let dir = tempdir().unwrap();
+            let config = RegistrationConfig {
+                applications_dir: Some(dir.path().to_path_buf()),
+                durability,
+                ..Default::default()
+            };
+            register_with_env(&handler, &config, &env).unwrap();
+            assert_eq!(
+                fs::read_to_string(dir.path().join("myapp.desktop")).unwrap(),
+                "[Desktop Entry]\nType=Application\nName=myapp\nExec=/opt/app/bin/app %u\nMimeType=x-scheme-handler/myapp\nX-ProtocolHandler-Managed=true"
+            );
+        }
+    }
+
+    #[test]
+    fn test_register_report() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let report =
+            register_with_env(&ProtocolHandler::new("myapp", "first"), &config, &env).unwrap();
+        assert_eq!(
+            report,
+            RegistrationReport {
+                path: path.clone(),
+                created: true,
+                managed: true,
+                exec_updated: false,
+            }
+        );
+        let report =
+            register_with_env(&ProtocolHandler::new("myapp", "second"), &config, &env).unwrap();
+        assert!(!report.created);
+        assert!(report.managed);
+
+        fs::write(&path, "[Desktop Entry]\nExec=app %u").unwrap();
+        let report =
+            register_with_env(&ProtocolHandler::new("myapp", "first"), &config, &env).unwrap();
+        assert_eq!(
+            report,
+            RegistrationReport {
+                path: path.clone(),
+                created: false,
+                managed: false,
+                exec_updated: false,
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/first"
+        );
+    }
+
+    #[test]
+    fn test_register_allowed_exec_prefixes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("myapp", "myapp").allowed_exec_prefixes(&["/usr"]);
+        assert!(matches!(
+            register_with_env(&handler, &config, &env),
+            Err(LinuxError::ExecNotAllowed(program)) if program == Path::new("/opt/app/bin/app")
+        ));
+        assert!(!path.exists());
+
+        let handler =
+            ProtocolHandler::new("myapp", "myapp").allowed_exec_prefixes(&["/usr", "/opt/app"]);
+        register_with_env(&handler, &config, &env).unwrap();
+        assert!(path.exists());
+
+        // Prefixes match whole components only.
+        let handler = ProtocolHandler::new("myapp", "myapp").allowed_exec_prefixes(&["/opt/ap"]);
+        assert!(register_with_env(&handler, &config, &env).is_err());
+    }
+
+    #[test]
+    fn test_register_all() {
+        let dir = tempdir().unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            run_db_update: true,
+            db_update_retries: 0,
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handlers = [
+            ProtocolHandler::new("first", "first"),
+            ProtocolHandler::new("bad", "-bad"),
+            ProtocolHandler::new("../escape", "escape"),
+            ProtocolHandler::new("second", "second"),
+        ];
+
+        let results = register_all_with_env(&handlers, &config, &env);
+        assert_eq!(results.len(), 4);
+        assert!(results[0].as_ref().unwrap().created);
+        assert!(matches!(results[1], Err(LinuxError::SchemeError(_))));
+        assert!(matches!(results[2], Err(LinuxError::InvalidName(_))));
+        assert_eq!(
+            results[3].as_ref().unwrap().path,
+            dir.path().join("second.desktop")
+        );
+        assert!(dir.path().join("first.desktop").exists());
+        assert!(!dir.path().join("bad.desktop").exists());
+    }
+
+    #[test]
+    fn test_register_failure_removes_new_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app1.desktop");
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("app1", "app1").command_template("/opt/app/run");
+        assert!(matches!(
+            register_with_env(&handler, &config, &env),
+            Err(LinuxError::ExecError(_))
+        ));
+        assert!(!path.exists());
+
+        let report =
+            register_with_env(&ProtocolHandler::new("app1", "app1"), &config, &env).unwrap();
+        assert!(report.created);
+        assert!(report.managed);
+    }
+
+    #[test]
+    fn test_register_from_template_marks_new_file() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let template = dir.path().join("template.desktop");
+        fs::write(&template, "[Desktop Entry]\nName=App").unwrap();
+        let path = dir.path().join(".local/share/applications/myapp.desktop");
+        let config = RegistrationConfig {
+            run_db_update: false,
+            ..Default::default()
+        };
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        register_from_template_with_env(&handler, &template, &config, &env).unwrap();
+        let de = DesktopEntry::try_from(fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(de.is_managed());
+        assert_eq!(
+            list_managed_entries_in(&[path.parent().unwrap().to_path_buf()]).unwrap(),
+            vec![path.clone()]
+        );
+
+        // Files that already exist keep their marker state.
+        fs::write(&path, "[Desktop Entry]\nName=Mine").unwrap();
+        register_from_template_with_env(&handler, &template, &config, &env).unwrap();
+        let de = DesktopEntry::try_from(fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(!de.is_managed());
+    }
+
+    #[test]
+    fn test_queries_use_config() {
+        let dir = tempdir().unwrap();
+        let home = tempdir().unwrap();
+        let home_str = home.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home_str), ("LANG", "")]);
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let default = RegistrationConfig::default();
+        register_with_env(&handler, &config, &env).unwrap();
+
+        assert!(is_registered_with_env(&handler, &config, &env).unwrap());
+        assert!(!is_registered_with_env(&handler, &default, &env).unwrap());
+        assert_eq!(
+            protocols_with_env(&handler, &config, &env).unwrap(),
+            vec!["myapp".parse::<Scheme>().unwrap()]
+        );
+        assert_eq!(
+            resolve_command_with_env(&handler, "myapp://x", &config, &env).unwrap(),
+            vec!["/opt/app/bin/app", "myapp://x"]
+        );
+        assert_eq!(
+            display_name_with_env(&handler, &config, &env).unwrap(),
+            Some("myapp".to_string())
+        );
+        let status = status_with_env(&handler, &config, &env).unwrap();
+        assert_eq!(status.path, dir.path().join("myapp.desktop"));
+        assert!(status.is_registered);
+        assert!(render_after_unregister_with_env(&handler, &config, &env)
+            .unwrap()
+            .contains("Name=myapp"));
+
+        rename_with_env(&handler, "renamed", &config, &env).unwrap();
+        assert!(dir.path().join("renamed.desktop").exists());
+        let renamed = ProtocolHandler::new("renamed", "myapp");
+        assert_eq!(
+            unregister_prefix_with_env(&renamed, "my", &config, &env).unwrap(),
+            vec!["myapp"]
+        );
+        assert!(!is_registered_with_env(&renamed, &config, &env).unwrap());
+    }
+
+    #[test]
+    fn test_register_refreshes_exec() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let mut env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        register_with_env(&handler, &config, &env).unwrap();
+
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let report = register_with_env(&handler, &config, &env).unwrap();
+        assert!(!report.exec_updated);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        env.exe = PathBuf::from("/opt/moved/bin/app");
+        let report = register_with_env(&handler, &config, &env).unwrap();
+        assert!(report.exec_updated);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nType=Application\nName=myapp\nExec=/opt/moved/bin/app %u\nMimeType=x-scheme-handler/myapp\nX-ProtocolHandler-Managed=true"
+        );
+
+        // Exec in files the user wrote is never replaced.
+        fs::write(&path, "[Desktop Entry]\nExec=app %u").unwrap();
+        let report = register_with_env(&handler, &config, &env).unwrap();
+        assert!(!report.exec_updated);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/myapp"
+        );
+    }
+
+    #[test]
+    fn test_register_creates_applications_dir() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        register_with_env(&handler, &RegistrationConfig::default(), &env).unwrap();
+        assert!(dir
+            .path()
+            .join(".local/share/applications/myapp.desktop")
+            .exists());
+    }
+
+    #[test]
+    fn test_register_applications_dir_permission_denied() {
+        let dir = tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o555)).unwrap();
+        if fs::create_dir(dir.path().join("probe")).is_ok() {
+            // Running as root, permissions are not enforced.
+            return;
+        }
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().join("applications")),
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        assert!(matches!(
+            register_with_env(&handler, &config, &env),
+            Err(LinuxError::DirError(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_register_icon_png() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().join("apps")),
+            run_db_update: false,
+            ..Default::default()
+        };
+        let icon = dir.path().join("icon.png");
+        let mut png: Vec<u8> = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&48u32.to_be_bytes());
+        png.extend_from_slice(&48u32.to_be_bytes());
+        fs::write(&icon, &png).unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let report = register_icon_with_env(&handler, &icon, &config, &env).unwrap();
+        assert_eq!(report.path, dir.path().join("apps/myapp.desktop"));
+        assert!(report.created);
+
+        assert_eq!(
+            fs::read(
+                dir.path()
+                    .join(".local/share/icons/hicolor/48x48/apps/myapp.png")
+            )
+            .unwrap(),
+            png
+        );
+        let content: String = fs::read_to_string(&report.path).unwrap();
+        assert!(content.contains("\nIcon=myapp"));
+        assert!(content.contains("\nMimeType=x-scheme-handler/myapp"));
+    }
+
+    #[test]
+    fn test_register_icon_svg() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let config = RegistrationConfig {
+            run_db_update: false,
+            ..Default::default()
+        };
+        let icon = dir.path().join("icon.svg");
+        fs::write(&icon, "<svg/>").unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        register_icon_with_env(&handler, &icon, &config, &env).unwrap();
+        assert!(dir
+            .path()
+            .join(".local/share/icons/hicolor/scalable/apps/myapp.svg")
+            .exists());
+    }
+
+    #[test]
+    fn test_register_icon_invalid() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let config = RegistrationConfig {
+            run_db_update: false,
+            ..Default::default()
+        };
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        let icon = dir.path().join("icon.png");
+        fs::write(&icon, "not a png, only text here").unwrap();
+        assert!(matches!(
+            register_icon_with_env(&handler, &icon, &config, &env),
+            Err(LinuxError::IconError(_))
+        ));
+
+        let icon = dir.path().join("icon.bmp");
+        fs::write(&icon, "BM").unwrap();
+        assert!(matches!(
+            register_icon_with_env(&handler, &icon, &config, &env),
+            Err(LinuxError::IconError(_))
+        ));
+    }
+
+    #[test]
+    fn test_register_append_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]\nExec=app %u\nMimeType=text/plain, x-scheme-handler/MyApp ;text/html",
+        )
+        .unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            append_only: true,
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        register_with_env(&ProtocolHandler::new("myapp", "myapp"), &config, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=text/plain, x-scheme-handler/MyApp ;text/html"
+        );
+
+        register_with_env(&ProtocolHandler::new("myapp", "other"), &config, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=text/plain, x-scheme-handler/MyApp ;text/html;x-scheme-handler/other"
+        );
+
+        assert!(matches!(
+            unregister_with_env(&ProtocolHandler::new("myapp", "other"), &config, &env),
+            Err(LinuxError::AppendOnly)
+        ));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("x-scheme-handler/other"));
+    }
+
+    #[test]
+    fn test_rename() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let content: &str =
+            "[Desktop Entry]\nName=Old\nExec=old %u\nMimeType=x-scheme-handler/myapp";
+        fs::write(applications.join("old.desktop"), content).unwrap();
+        let mimeapps = dir.path().join(".config/mimeapps.list");
+        fs::create_dir_all(mimeapps.parent().unwrap()).unwrap();
+        fs::write(
+            &mimeapps,
+            "[Default Applications]\nx-scheme-handler/myapp=old.desktop\ntext/plain=editor.desktop",
+        )
+        .unwrap();
+
+        let handler = ProtocolHandler::new("old", "myapp");
+        rename_with_env(&handler, "new", &RegistrationConfig::default(), &env).unwrap();
+
+        assert!(!applications.join("old.desktop").exists());
+        assert_eq!(
+            fs::read_to_string(applications.join("new.desktop")).unwrap(),
+            content
+        );
+        assert_eq!(
+            fs::read_to_string(&mimeapps).unwrap(),
+            "[Default Applications]\nx-scheme-handler/myapp=new.desktop\ntext/plain=editor.desktop\n"
+        );
+
+        fs::write(applications.join("old.desktop"), content).unwrap();
+        assert!(rename_with_env(&handler, "new", &RegistrationConfig::default(), &env).is_err());
+        assert!(applications.join("old.desktop").exists());
+    }
+
+    #[test]
+    fn test_render_after_unregister() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let content: &str =
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/myapp;x-scheme-handler/other";
+        fs::write(applications.join("myapp.desktop"), content).unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert_eq!(
+            render_after_unregister_with_env(&handler, &RegistrationConfig::default(), &env)
+                .unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/other"
+        );
+        assert_eq!(
+            fs::read_to_string(applications.join("myapp.desktop")).unwrap(),
+            content
+        );
+
+        let handler = ProtocolHandler::new("missing", "myapp");
+        assert!(
+            render_after_unregister_with_env(&handler, &RegistrationConfig::default(), &env)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_unregister_missing_file() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert!(
+            unregister_with_env(&handler, &RegistrationConfig::default(), &env)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(!applications.join("myapp.desktop").exists());
+    }
+
+    #[test]
+    fn test_unregister_writes_file() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/myapp;text/plain",
+        )
+        .unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert_eq!(
+            unregister_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            vec!["myapp"]
+        );
+        assert_eq!(
+            fs::read_to_string(applications.join("myapp.desktop")).unwrap(),
+            "[Desktop Entry]\nExec=app %u\nMimeType=text/plain"
+        );
+        assert_eq!(
+            unregister_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_dedup_lists() {
+        let mut de: DesktopEntry = "[Desktop Entry]\nMimeType=text/plain;x-scheme-handler/a;text/plain;x-scheme-handler/a\nCategories=Network;WebBrowser;Network;\nKeywords[de]=web;web\nActions=new;new;"
+            .parse()
+            .unwrap();
+        de.dedup_lists(&RegistrationConfig::default().dedup_keys);
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nMimeType=text/plain;x-scheme-handler/a\nCategories=Network;WebBrowser;\nKeywords[de]=web\nActions=new;new;"
+        );
+    }
+
+    #[test]
+    fn test_set_scheme_handlers() {
+        let mut de: DesktopEntry =
+            "[Desktop Entry]\nMimeType=x-scheme-handler/a;text/plain;x-scheme-handler/b;text/html"
+                .parse()
+                .unwrap();
+        de.set_scheme_handlers(&["b", "C", "c"]);
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nMimeType=text/plain;text/html;x-scheme-handler/b;x-scheme-handler/c"
+        );
+
+        de.set_scheme_handlers(&[]);
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nMimeType=text/plain;text/html"
+        );
+
+        let mut de = DesktopEntry::default();
+        de.set_scheme_handlers(&[]);
+        assert_eq!(de.to_string(), "[Desktop Entry]\n");
+    }
+
+    #[test]
+    fn test_delete_scheme_handlers_with_prefix() {
+        let mut de: DesktopEntry =
+            "[Desktop Entry]\nMimeType=x-scheme-handler/suite.tool1;text/plain;x-scheme-handler/Suite.Tool2;x-scheme-handler/other"
+                .parse()
+                .unwrap();
+        de.delete_scheme_handlers_with_prefix("suite.").unwrap();
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nMimeType=text/plain;x-scheme-handler/other"
+        );
+
+        for prefix in ["", "-suite", "su ite"] {
+            assert!(matches!(
+                de.delete_scheme_handlers_with_prefix(prefix),
+                Err(LinuxError::SchemeError(x)) if x == prefix
+            ));
+        }
+        assert_eq!(
+            de.to_string(),
+            "[Desktop Entry]\nMimeType=text/plain;x-scheme-handler/other"
+        );
+    }
+
+    #[test]
+    fn test_unregister_prefix() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        fs::write(
+            applications.join("suite.desktop"),
+            "[Desktop Entry]\nExec=suite %u\nMimeType=x-scheme-handler/suite.tool1;x-scheme-handler/suite.tool2;x-scheme-handler/suite.tool3;x-scheme-handler/unrelated",
+        )
+        .unwrap();
+
+        let handler = ProtocolHandler::new("suite", "suite.tool1");
+        assert_eq!(
+            unregister_prefix_with_env(&handler, "suite.", &RegistrationConfig::default(), &env)
+                .unwrap(),
+            vec!["suite.tool1", "suite.tool2", "suite.tool3"]
+        );
+        assert_eq!(
+            fs::read_to_string(applications.join("suite.desktop")).unwrap(),
+            "[Desktop Entry]\nExec=suite %u\nMimeType=x-scheme-handler/unrelated"
+        );
+        assert!(matches!(
+            unregister_prefix_with_env(&handler, "", &RegistrationConfig::default(), &env),
+            Err(LinuxError::SchemeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_unregister_prefix_append_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("suite.desktop");
+        let content: &str = "[Desktop Entry]\nExec=suite %u\nMimeType=x-scheme-handler/suite.tool1";
+        fs::write(&path, content).unwrap();
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            append_only: true,
+            ..Default::default()
+        };
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+
+        let handler = ProtocolHandler::new("suite", "suite.tool1");
+        assert!(matches!(
+            unregister_prefix_with_env(&handler, "suite.", &config, &env),
+            Err(LinuxError::AppendOnly)
+        ));
+        assert!(matches!(
+            purge_with_env(&handler, &config, &env),
+            Err(LinuxError::AppendOnly)
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_value_containing_equals() {
+        let de: DesktopEntry = "[Desktop Entry]\nExec=flatpak run --branch=stable app %u"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(&"flatpak run --branch=stable app %u".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_error_line() {
+        let err = DesktopEntry::from_str("[Desktop Entry]\nName=App\n# note\nExec").unwrap_err();
+        assert!(matches!(
+            &err,
+            LinuxError::ParseError { line: 4, message } if message == "Invalid field format: Exec"
+        ));
+        assert_eq!(err.to_string(), "Line 4: Invalid field format: Exec");
+
+        let err = DesktopEntry::from_str("Name=App").unwrap_err();
+        assert!(matches!(err, LinuxError::ParseError { line: 1, .. }));
+
+        let err = DesktopEntry::from_str("[Desktop Entry]\n[A]\nX=1\n[A]").unwrap_err();
+        assert!(matches!(err, LinuxError::ParseError { line: 4, .. }));
+    }
+
+    #[test]
+    fn test_from_bytes_legacy_encoding() {
+        let bytes: Vec<u8> = b"[Desktop Entry]\nEncoding=Legacy-Mixed\nName=Caf\xe9".to_vec();
+        assert!(DesktopEntry::from_bytes(bytes.clone(), false).is_err());
+
+        let de = DesktopEntry::from_bytes(bytes, true).unwrap();
+        assert_eq!(de.data.get("Name"), Some(&"Caf\u{e9}".to_string()));
+        assert_eq!(de.data.get("Encoding"), Some(&"UTF-8".to_string()));
+
+        let de = DesktopEntry::from_bytes("[Desktop Entry]\nName=Caf\u{e9}".into(), true).unwrap();
+        assert_eq!(de.data.get("Name"), Some(&"Caf\u{e9}".to_string()));
+    }
+
+    #[test]
+    fn test_register_legacy_encoding() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        fs::write(&path, b"[Desktop Entry]\nName=Caf\xe9\nExec=app %u").unwrap();
+        let env = FakeEnvironment::new(&[("HOME", "/nonexistent")]);
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let mut config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        assert!(register_with_env(&handler, &config, &env).is_err());
+
+        config.legacy_encoding = true;
+        register_with_env(&handler, &config, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Desktop Entry]\nName=Caf\u{e9}\nExec=app %u\nMimeType=x-scheme-handler/myapp"
+        );
+    }
+
+    #[test]
+    fn test_bom() {
+        let content: String = "\u{feff}[Desktop Entry]\nExec=app %u".to_string();
+        let mut de = DesktopEntry::try_from(content).unwrap();
+        assert_eq!(de.data.get("Exec"), Some(&"app %u".to_string()));
+
+        de.insert_scheme_handler("x-scheme-handler/app".to_string());
+        assert_eq!(
+            de.to_string(),
+            "\u{feff}[Desktop Entry]\nExec=app %u\nMimeType=x-scheme-handler/app"
+        );
+    }
+
+    #[test]
+    fn test_from_reader_strict() {
+        assert!(DesktopEntry::from_reader("".as_bytes()).is_ok());
+        assert!(DesktopEntry::from_reader_strict("".as_bytes()).is_err());
+        assert!(DesktopEntry::from_reader_strict("Exec=app %u\nName=App".as_bytes()).is_err());
+        assert!(DesktopEntry::from_reader_strict("[Desktop Entry]\nName=App".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_default_handler_name() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let data_dirs = dir.path().join("share").to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[
+            ("HOME", &home),
+            ("XDG_CONFIG_DIRS", &home),
+            ("XDG_DATA_DIRS", &data_dirs),
+            ("LANG", "de_DE.UTF-8"),
+        ]);
+        assert_eq!(default_handler_name_with_env("myapp", &env).unwrap(), None);
+
+        fs::create_dir_all(dir.path().join(".config")).unwrap();
+        fs::write(
+            dir.path().join(".config/mimeapps.list"),
+            "[Default Applications]\nx-scheme-handler/myapp=browser.desktop",
+        )
+        .unwrap();
+        assert_eq!(default_handler_name_with_env("myapp", &env).unwrap(), None);
+
+        let applications = dir.path().join("share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        fs::write(
+            applications.join("browser.desktop"),
+            "[Desktop Entry]\nName=Browser\nName[de]=Netzbetrachter",
+        )
+        .unwrap();
+        assert_eq!(
+            default_handler_name_with_env("myapp", &env).unwrap(),
+            Some("Netzbetrachter".to_string())
         );
     }
 
     #[test]
-    fn test_insert_scheme_handler_has_mime_type() {
+    fn test_is_registered_anywhere() {
+        let user = tempdir().unwrap();
+        let system = tempdir().unwrap();
+        fs::write(
+            system.path().join("other.desktop"),
+            "[Desktop Entry]\nMimeType=text/html;x-scheme-handler/myapp;",
+        )
+        .unwrap();
+        fs::write(user.path().join("broken.desktop"), "garbage").unwrap();
+        let dirs = vec![user.path().to_path_buf(), system.path().to_path_buf()];
+
+        assert!(is_registered_anywhere_in(&dirs, "myapp").unwrap());
+        assert!(is_registered_anywhere_in(&dirs, "MyApp").unwrap());
+        assert!(!is_registered_anywhere_in(&dirs, "other").unwrap());
+        assert!(!is_registered_anywhere_in(&dirs[..1], "myapp").unwrap());
+    }
+
+    #[test]
+    fn test_scheme_handler_map() {
+        let user = tempdir().unwrap();
+        let system = tempdir().unwrap();
+        fs::write(
+            user.path().join("first.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp;x-scheme-handler/mailto;",
+        )
+        .unwrap();
+        fs::write(user.path().join("broken.desktop"), "garbage").unwrap();
+        fs::write(
+            system.path().join("second.desktop"),
+            "[Desktop Entry]\nMimeType=text/html;x-scheme-handler/MyApp;",
+        )
+        .unwrap();
+        fs::write(
+            system.path().join("first.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp;",
+        )
+        .unwrap();
+        let dirs = vec![user.path().to_path_buf(), system.path().to_path_buf()];
+
+        let map = scheme_handler_map_in(&dirs).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["myapp"], vec!["first.desktop", "second.desktop"]);
+        assert_eq!(map["mailto"], vec!["first.desktop"]);
+        assert!(scheme_handler_map_in(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_managed_entries() {
+        let user = tempdir().unwrap();
+        let system = tempdir().unwrap();
+        fs::write(
+            user.path().join("managed.desktop"),
+            format!("[Desktop Entry]\nName=app\n{MANAGED_KEY}=true"),
+        )
+        .unwrap();
+        fs::write(
+            user.path().join("unmanaged.desktop"),
+            "[Desktop Entry]\nName=app",
+        )
+        .unwrap();
+        fs::write(user.path().join("broken.desktop"), "not a desktop entry").unwrap();
+        fs::write(
+            user.path().join("notes.txt"),
+            format!("[Desktop Entry]\n{MANAGED_KEY}=true"),
+        )
+        .unwrap();
+        fs::write(
+            system.path().join("other.desktop"),
+            format!("[Desktop Entry]\n{MANAGED_KEY}=true"),
+        )
+        .unwrap();
+
+        let dirs = vec![
+            user.path().to_path_buf(),
+            system.path().to_path_buf(),
+            user.path().join("missing"),
+        ];
+        assert_eq!(
+            list_managed_entries_in(&dirs).unwrap(),
+            vec![
+                user.path().join("managed.desktop"),
+                system.path().join("other.desktop"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_purge() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let config = RegistrationConfig {
+            run_db_update: false,
+            ..Default::default()
+        };
+        let handler = ProtocolHandler::new("myapp", "myapp").set_as_default(true);
+        let report = register_with_env(&handler, &config, &env).unwrap();
+        let mimeapps_path = dir.path().join(".config/mimeapps.list");
+        fs::write(
+            &mimeapps_path,
+            "[Added Associations]\nx-scheme-handler/myapp=other.desktop;myapp.desktop;\n[Default Applications]\nx-scheme-handler/myapp=myapp.desktop\ntext/html=browser.desktop\n",
+        )
+        .unwrap();
+
+        assert!(purge_with_env(&handler, &config, &env).unwrap());
+        assert!(!report.path.exists());
+        assert_eq!(
+            fs::read_to_string(&mimeapps_path).unwrap(),
+            "[Added Associations]\nx-scheme-handler/myapp=other.desktop\n[Default Applications]\ntext/html=browser.desktop\n"
+        );
+        assert!(!purge_with_env(&handler, &config, &env).unwrap());
+    }
+
+    #[test]
+    fn test_purge_unmanaged() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let path = applications.join("myapp.desktop");
+        fs::write(&path, "[Desktop Entry]\nMimeType=x-scheme-handler/myapp").unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert!(matches!(
+            purge_with_env(&handler, &RegistrationConfig::default(), &env),
+            Err(LinuxError::NotManaged(_))
+        ));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_declares_scheme() {
+        assert!(declares_scheme(
+            "[Desktop Entry]\nMimeType=text/html;x-scheme-handler/myapp;",
+            "myapp"
+        )
+        .unwrap());
+        assert!(!declares_scheme(
+            "[Desktop Entry]\n# MimeType=x-scheme-handler/myapp;\nName=x-scheme-handler/myapp",
+            "myapp"
+        )
+        .unwrap());
+        assert!(!declares_scheme(
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp2;\n[Desktop Action new]\nMimeType=x-scheme-handler/myapp",
+            "myapp"
+        )
+        .unwrap());
+        assert!(!declares_scheme(
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp;\nHidden=true",
+            "myapp"
+        )
+        .unwrap());
+        assert!(declares_scheme(
+            "[Desktop Action new]\nName=New\n[Desktop Entry]\nMimeType=x-scheme-handler/myapp",
+            "myapp"
+        )
+        .unwrap());
+        assert!(declares_scheme("garbage", "myapp").is_err());
+    }
+
+    #[test]
+    fn test_is_registered_read_only() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let path = applications.join("myapp.desktop");
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        assert!(!is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+        assert!(!path.exists());
+
+        fs::write(&path, "[Desktop Entry]\nMimeType=x-scheme-handler/myapp").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+        fs::set_permissions(&applications, fs::Permissions::from_mode(0o555)).unwrap();
+        let registered = is_registered_with_env(&handler, &RegistrationConfig::default(), &env);
+        fs::set_permissions(&applications, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(registered.unwrap());
+        assert!(!is_registered_with_env(
+            &ProtocolHandler::new("other", "myapp"),
+            &RegistrationConfig::default(),
+            &env
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_is_registered_hidden() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp\nHidden=true",
+        )
+        .unwrap();
+        assert!(!is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/myapp\nNoDisplay=true",
+        )
+        .unwrap();
+        assert!(is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+    }
+
+    #[test]
+    fn test_protocols() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert_eq!(
+            protocols_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Vec::new()
+        );
+
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/MyApp;text/plain;x-scheme-handler/web+app;x-scheme-handler/1bad",
+        )
+        .unwrap();
+        assert_eq!(
+            protocols_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            vec![
+                "myapp".parse::<Scheme>().unwrap(),
+                "web+app".parse::<Scheme>().unwrap()
+            ]
+        );
+
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nMimeType=text/plain",
+        )
+        .unwrap();
+        assert_eq!(
+            protocols_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_enumerate_scheme_handlers() {
+        let dir = tempdir().unwrap();
+        let user = dir.path().join("user");
+        let system = dir.path().join("system");
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::write(
+            user.join("mail.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/mailto;text/plain",
+        )
+        .unwrap();
+        fs::write(user.join("broken.desktop"), "not a desktop file").unwrap();
+        fs::write(
+            user.join("notes.txt"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/notes",
+        )
+        .unwrap();
+        fs::write(
+            system.join("browser.desktop"),
+            "[Desktop Entry]\nMimeType=x-scheme-handler/http;x-scheme-handler/https",
+        )
+        .unwrap();
+        fs::write(
+            system.join("editor.desktop"),
+            "[Desktop Entry]\nMimeType=text/plain",
+        )
+        .unwrap();
+
+        let dirs = vec![user.clone(), system, dir.path().join("missing")];
+        let report = enumerate_scheme_handlers_in(&dirs, MalformedPolicy::Skip).unwrap();
+        assert_eq!(
+            report.results,
+            vec![
+                ("mailto".to_string(), "mail.desktop".to_string()),
+                ("http".to_string(), "browser.desktop".to_string()),
+                ("https".to_string(), "browser.desktop".to_string()),
+            ]
+        );
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, user.join("broken.desktop"));
+        assert!(matches!(
+            report.errors[0].1,
+            LinuxError::ParseError { line: 1, .. }
+        ));
+
+        assert!(matches!(
+            enumerate_scheme_handlers_in(&dirs, MalformedPolicy::Error),
+            Err(LinuxError::ParseError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_handler_from_desktop_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("org.example.App.desktop");
+        fs::write(
+            &path,
+            "[Desktop Entry]\nExec=app %u\nMimeType=text/plain;x-scheme-handler/example;x-scheme-handler/other",
+        )
+        .unwrap();
+        let handler = handler_from_desktop_file(&path).unwrap();
+        assert_eq!(handler.name, "org.example.App");
+        assert_eq!(handler.protocol_name, "example");
+
+        fs::write(&path, "[Desktop Entry]\nExec=app").unwrap();
+        let handler = handler_from_desktop_file(&path).unwrap();
+        assert_eq!(handler.protocol_name, "");
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(matches!(
+            handler_from_desktop_file(&path),
+            Err(LinuxError::ParseError { .. })
+        ));
+
+        let path = dir.path().join("app.txt");
+        fs::write(&path, "[Desktop Entry]").unwrap();
+        assert!(matches!(
+            handler_from_desktop_file(&path),
+            Err(LinuxError::NotDesktopFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_localized_name() {
+        let de: DesktopEntry = "[Desktop Entry]\nName=App\nName[de]=Anwendung\nName[pt_BR]=Aplicativo\nName[sr@latin]=Aplikacija"
+            .parse()
+            .unwrap();
+        assert_eq!(de.localized_name(None), Some("App"));
+        assert_eq!(de.localized_name(Some("C")), Some("App"));
+        assert_eq!(de.localized_name(Some("de_DE.UTF-8")), Some("Anwendung"));
+        assert_eq!(de.localized_name(Some("pt_BR.UTF-8")), Some("Aplicativo"));
+        assert_eq!(de.localized_name(Some("pt_PT.UTF-8")), Some("App"));
+        assert_eq!(de.localized_name(Some("sr_RS@latin")), Some("Aplikacija"));
+        assert_eq!(de.localized_name(Some("fr_FR")), Some("App"));
+    }
+
+    #[test]
+    fn test_display_name_uses_lang() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nName=My App\nName[de]=Meine App\nName[fr]=Mon App",
+        )
+        .unwrap();
+        let handler = ProtocolHandler::new("myapp", "myapp");
+
+        let env = FakeEnvironment::new(&[("HOME", &home), ("LANG", "fr_FR.UTF-8")]);
+        assert_eq!(
+            display_name_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Some("Mon App".to_string())
+        );
+
+        let env = FakeEnvironment::new(&[
+            ("HOME", &home),
+            ("LANG", "fr_FR.UTF-8"),
+            ("LC_MESSAGES", "de_DE.UTF-8"),
+        ]);
+        assert_eq!(
+            display_name_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Some("Meine App".to_string())
+        );
+
+        let env = FakeEnvironment::new(&[("HOME", &home), ("LANG", "es_ES.UTF-8")]);
+        assert_eq!(
+            display_name_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            Some("My App".to_string())
+        );
+
+        let handler = ProtocolHandler::new("other", "other");
+        assert_eq!(
+            display_name_with_env(&handler, &RegistrationConfig::default(), &env).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_display_name_for_locale() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home), ("LANG", "fr_FR.UTF-8")]);
+        let applications = dir.path().join(".local/share/applications");
+        fs::create_dir_all(&applications).unwrap();
+        fs::write(
+            applications.join("myapp.desktop"),
+            "[Desktop Entry]\nName=Colour App\nName[en_US]=Color App\nName[sr@latin]=Aplikacija\nName[sr]=\u{410}\u{43f}\nName[pt]=App Cor",
+        )
+        .unwrap();
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let name = |locale: &str| {
+            display_name_for_locale_with_env(
+                &handler,
+                Some(locale),
+                &RegistrationConfig::default(),
+                &env,
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        assert_eq!(name("en_US.UTF-8@euro"), "Color App");
+        assert_eq!(name("en_US"), "Color App");
+        assert_eq!(name("en_GB.UTF-8"), "Colour App");
+        assert_eq!(name("sr_RS@latin"), "Aplikacija");
+        assert_eq!(name("sr_RS.UTF-8"), "\u{410}\u{43f}");
+        assert_eq!(name("pt_BR.UTF-8"), "App Cor");
+        assert_eq!(name("C"), "Colour App");
+    }
+
+    #[test]
+    fn test_expand_exec() {
+        let de = DesktopEntry::try_from(
+            "[Desktop Entry]\nName=My App\nIcon=myapp\nExec=app".to_string(),
+        )
+        .unwrap();
+        let path = Path::new("/usr/share/applications/myapp.desktop");
+        let expand = |exec: &str| expand_exec(exec, "myapp://x", &de, path);
+
+        assert_eq!(expand("app %u").unwrap(), vec!["app", "myapp://x"]);
+        assert_eq!(
+            expand("\"/opt/My App/run\" --url=%u").unwrap(),
+            vec!["/opt/My App/run", "--url=myapp://x"]
+        );
+        assert_eq!(
+            expand("run \"say \\\\\"hi\\\\\"\" %U").unwrap(),
+            vec!["run", "say \"hi\"", "myapp://x"]
+        );
+        assert!(expand("\"/opt/app %u").is_err());
+    }
+
+    #[test]
+    fn test_expand_exec_spec_examples() {
+        let de = DesktopEntry::try_from(
+            "[Desktop Entry]\nName=My App\nIcon=myapp\nExec=app".to_string(),
+        )
+        .unwrap();
+        let path = Path::new("/usr/share/applications/myapp.desktop");
+        let expand = |exec: &str| expand_exec(exec, "myapp://x", &de, path);
+
+        // A literal backslash inside quotes is written with four in the file.
+        assert_eq!(expand("app \"\\\\\\\\\"").unwrap(), vec!["app", "\\"]);
+        assert_eq!(expand("app \"\\\\$HOME\"").unwrap(), vec!["app", "$HOME"]);
+        assert_eq!(
+            expand("\"my\\sapp\" %u").unwrap(),
+            vec!["my app", "myapp://x"]
+        );
+        assert_eq!(
+            expand("app --progress=50%% %u").unwrap(),
+            vec!["app", "--progress=50%", "myapp://x"]
+        );
+        assert_eq!(
+            expand("app %i --title=%c %k %u").unwrap(),
+            vec![
+                "app",
+                "--icon",
+                "myapp",
+                "--title=My App",
+                "/usr/share/applications/myapp.desktop",
+                "myapp://x"
+            ]
+        );
+        assert_eq!(expand("app %d %m %f %u").unwrap(), vec!["app", "myapp://x"]);
+        assert!(expand("app %z").is_err());
+        assert!(expand("app --files=%F").is_err());
+        assert!(expand("app 100%").is_err());
+    }
+
+    #[test]
+    fn test_resolve_command() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        let handler = ProtocolHandler::new("myapp", "myapp")
+            .extra_key("Exec", "\"/opt/My App/app\" --open %u");
+        register_with_env(&handler, &RegistrationConfig::default(), &env).unwrap();
+
+        assert_eq!(
+            resolve_command_with_env(
+                &handler,
+                "myapp://open?id=1",
+                &RegistrationConfig::default(),
+                &env
+            )
+            .unwrap(),
+            vec!["/opt/My App/app", "--open", "myapp://open?id=1"]
+        );
+
+        let missing = ProtocolHandler::new("missing", "myapp");
+        let path = dir.path().join(".local/share/applications/missing.desktop");
+        let err =
+            resolve_command_with_env(&missing, "myapp://", &RegistrationConfig::default(), &env)
+                .unwrap_err();
+        assert!(matches!(&err, LinuxError::Io { path: x, .. } if *x == path));
+        assert!(err.to_string().starts_with(&path.display().to_string()));
+        let err = render_after_unregister_with_env(&missing, &RegistrationConfig::default(), &env)
+            .unwrap_err();
+        assert!(matches!(err, LinuxError::Io { path: x, .. } if x == path));
+    }
+
+    #[test]
+    fn test_quote_exec_arg() {
+        assert_eq!(quote_exec_arg("/opt/app"), "/opt/app");
+        assert_eq!(quote_exec_arg("my app"), "\"my app\"");
+        assert_eq!(quote_exec_arg("echo \"$HOME\""), "\"echo \\\"\\$HOME\\\"\"");
+    }
+
+    #[test]
+    fn test_prepare_entry_wrapper_command() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp").wrapper_command("export FOO=1");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        prepare_entry(&mut de, &handler, &env).unwrap();
+        assert_eq!(
+            de.data.get("Exec"),
+            Some(
+                &"/bin/sh -c \"export FOO=1; exec '/opt/app/bin/app' \\\\\"\\\\$@\\\\\"\" _ %u"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_prepare_entry_wrapper_command_field_code() {
+        let env = FakeEnvironment::new(&[]);
+        let handler = ProtocolHandler::new("myapp", "myapp").wrapper_command("run %u");
+        let mut de = DesktopEntry::from_reader("[Desktop Entry]".as_bytes()).unwrap();
+        assert!(matches!(
+            prepare_entry(&mut de, &handler, &env),
+            Err(LinuxError::ExecError(_))
+        ));
+    }
+
+    #[test]
+    fn test_register_fake_home() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home)]);
+        fs::create_dir_all(dir.path().join(".local/share/applications")).unwrap();
+
+        let handler = ProtocolHandler::new("myapp", "myapp").set_as_default(true);
+        register_with_env(&handler, &RegistrationConfig::default(), &env).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".local/share/applications/myapp.desktop")).unwrap(),
+            "[Desktop Entry]\nType=Application\nName=myapp\nExec=/opt/app/bin/app %u\nMimeType=x-scheme-handler/myapp\nX-ProtocolHandler-Managed=true"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".config/mimeapps.list")).unwrap(),
+            "[Default Applications]\nx-scheme-handler/myapp=myapp.desktop\n"
+        );
+        assert!(is_registered_with_env(&handler, &RegistrationConfig::default(), &env).unwrap());
+        assert!(!is_registered_with_env(
+            &ProtocolHandler::new("myapp", "other"),
+            &RegistrationConfig::default(),
+            &env
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_applications_dir_scope() {
+        let config = RegistrationConfig {
+            scope: Scope::System,
+            ..Default::default()
+        };
+        assert_eq!(
+            get_applications_dir(&config, &HostEnvironment).unwrap(),
+            PathBuf::from("/usr/share/applications")
+        );
+
+        let config = RegistrationConfig {
+            scope: Scope::System,
+            applications_dir: Some(PathBuf::from("/opt/share/applications")),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_applications_dir(&config, &HostEnvironment).unwrap(),
+            PathBuf::from("/opt/share/applications")
+        );
+    }
+
+    #[test]
+    fn test_register_with_config_applications_dir() {
+        let dir = tempdir().unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            host_exe: Some(env::current_exe().unwrap()),
+            ..Default::default()
+        };
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        register_with_config(&handler, &config).unwrap();
+
+        let content = fs::read_to_string(dir.path().join("myapp.desktop")).unwrap();
+        assert!(content.contains("MimeType=x-scheme-handler/myapp"));
+        assert!(!dir.path().join("myapp.desktop.bak").exists());
+    }
+
+    #[test]
+    fn test_register_with_config_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("myapp.desktop");
+        fs::write(&path, "[Desktop Entry]\nName=My App").unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            host_exe: Some(env::current_exe().unwrap()),
+            ..Default::default()
+        };
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            create_backup: true,
+            ..Default::default()
+        };
+        register_with_config(&handler, &config).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("myapp.desktop.bak")).unwrap(),
+            "[Desktop Entry]\nName=My App"
+        );
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("MimeType=x-scheme-handler/myapp"));
+    }
+
+    #[test]
+    fn test_register_with_config_backup_new_file() {
+        let dir = tempdir().unwrap();
+        let handler = ProtocolHandler {
+            name: "myapp".to_string(),
+            protocol_name: "myapp".to_string(),
+            host_exe: Some(env::current_exe().unwrap()),
+            ..Default::default()
+        };
+        let config = RegistrationConfig {
+            applications_dir: Some(dir.path().to_path_buf()),
+            create_backup: true,
+            run_db_update: true,
+            ..Default::default()
+        };
+        register_with_config(&handler, &config).unwrap();
+
+        assert!(dir.path().join("myapp.desktop").exists());
+        assert!(!dir.path().join("myapp.desktop.bak").exists());
+    }
+
+    #[test]
+    fn test_run_with_retries_transient_failure() {
+        let dir = tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg("[ -e \"$0\" ] && exit 0; touch \"$0\"; exit 1")
+            .arg(&marker);
+
+        run_with_retries(&mut command, 2, Duration::from_millis(1)).unwrap();
+        assert!(marker.exists());
+    }
+
+    #[test]
+    fn test_run_with_retries_exhausted() {
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg("exit 1");
+        assert!(matches!(
+            run_with_retries(&mut command, 2, Duration::from_millis(1)),
+            Err(LinuxError::CommandError(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_with_retries_not_installed() {
+        let dir = tempdir().unwrap();
+        let mut command = Command::new(dir.path().join("update-desktop-database"));
+        run_with_retries(&mut command, 2, Duration::from_secs(60)).unwrap();
+    }
+
+    #[test]
+    fn test_reset_to_default() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mimeapps.list");
+        let paths = vec![path.clone()];
+
+        set_default_handler_at(&path, "myapp", "original.desktop").unwrap();
+        let previous = default_handler_in(&paths, "myapp").unwrap();
+        assert_eq!(previous, Some("original.desktop".to_string()));
+
+        set_default_handler_at(&path, "myapp", "myapp.desktop").unwrap();
+        assert_eq!(
+            default_handler_in(&paths, "myapp").unwrap(),
+            Some("myapp.desktop".to_string())
+        );
+
+        reset_to_default_at(&path, "myapp", previous).unwrap();
+        assert_eq!(
+            default_handler_in(&paths, "myapp").unwrap(),
+            Some("original.desktop".to_string())
+        );
+
+        reset_to_default_at(&path, "myapp", None).unwrap();
+        assert_eq!(default_handler_in(&paths, "myapp").unwrap(), None);
+    }
+
+    #[test]
+    fn test_default_handler_in() {
+        let dir = tempdir().unwrap();
+        let user = dir.path().join("user.list");
+        let system = dir.path().join("system.list");
+        fs::write(
+            &user,
+            "[Default Applications]\nx-scheme-handler/mailto=mail.desktop",
+        )
+        .unwrap();
+        fs::write(
+            &system,
+            "[Default Applications]\nx-scheme-handler/mailto=other.desktop\nx-scheme-handler/myapp=myapp.desktop;",
+        )
+        .unwrap();
+        let paths = vec![user, dir.path().join("missing.list"), system];
+
+        assert_eq!(
+            default_handler_in(&paths, "mailto").unwrap(),
+            Some("mail.desktop".to_string())
+        );
+        assert_eq!(
+            default_handler_in(&paths, "MyApp").unwrap(),
+            Some("myapp.desktop".to_string())
+        );
+        assert_eq!(default_handler_in(&paths, "unknown").unwrap(), None);
+    }
+
+    #[test]
+    fn test_mime_type_comma_separated() {
         let content: String =
-            "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=application/cdf".to_string();
+            "[Desktop Entry]\nMimeType=application/cdf,x-scheme-handler/app".to_string();
         let mut de = DesktopEntry::try_from(content).unwrap();
-        de.insert_scheme_handler("x-scheme-handler/app".to_string());
+        assert!(de.contains_mime_type("x-scheme-handler/app"));
+
+        de.insert_scheme_handler("x-scheme-handler/app2".to_string());
         assert_eq!(
             de.data.get("MimeType"),
-            Some(&"application/cdf;x-scheme-handler/app".to_string())
+            Some(&"application/cdf;x-scheme-handler/app;x-scheme-handler/app2".to_string())
         );
     }
 
     #[test]
-    fn test_insert_scheme_handler_replace() {
+    fn test_mime_type_space_separated() {
         let content: String =
-            "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=x-scheme-handler/app".to_string();
+            "[Desktop Entry]\nMimeType=application/cdf x-scheme-handler/app; text/plain"
+                .to_string();
         let mut de = DesktopEntry::try_from(content).unwrap();
-        de.insert_scheme_handler("x-scheme-handler/app2".to_string());
+        assert!(de.contains_mime_type("x-scheme-handler/app"));
+
+        de.delete_scheme_handler("x-scheme-handler/app");
         assert_eq!(
             de.data.get("MimeType"),
-            Some(&"x-scheme-handler/app2".to_string())
+            Some(&"application/cdf;text/plain".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_default_conflict() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mimeapps.list");
+        fs::write(
+            &path,
+            "[Default Applications]\nx-scheme-handler/myapp=other.desktop",
+        )
+        .unwrap();
+        let paths = vec![path];
+
+        let handler = ProtocolHandler::new("myapp", "myapp").take_over_default(false);
+        assert!(matches!(
+            check_default_conflict(&paths, &handler),
+            Err(LinuxError::SchemeAlreadyDefault(id)) if id == "other.desktop"
+        ));
+
+        let handler = ProtocolHandler::new("other", "myapp").take_over_default(false);
+        assert!(check_default_conflict(&paths, &handler).is_ok());
+
+        let handler = ProtocolHandler::new("myapp", "another").take_over_default(false);
+        assert!(check_default_conflict(&paths, &handler).is_ok());
+
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        assert!(check_default_conflict(&paths, &handler).is_ok());
+    }
+
+    #[test]
+    fn test_take_over_default() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mimeapps.list");
+        fs::write(
+            &path,
+            "[Default Applications]\nx-scheme-handler/myapp=other.desktop",
+        )
+        .unwrap();
+
+        let paths = vec![path];
+
+        let handler = ProtocolHandler::new("myapp", "myapp").take_over_default(true);
+        assert!(check_default_conflict(&paths, &handler).is_ok());
+        update_default_at(&paths[0], &handler).unwrap();
+        assert_eq!(
+            default_handler_in(&paths, "myapp").unwrap(),
+            Some("myapp.desktop".to_string())
         );
     }
 
@@ -238,7 +5444,7 @@ mod tests {
         let content: String =
             "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=x-scheme-handler/app".to_string();
         let mut de = DesktopEntry::try_from(content).unwrap();
-        de.delete_scheme_handler();
+        de.delete_scheme_handler("x-scheme-handler/app");
         assert!(!de.data.contains_key("MimeType"));
     }
 
@@ -247,7 +5453,7 @@ mod tests {
         let content: String =
             "[Desktop Entry]\nfield1=val1\nfield2=val2\nMimeType=x-scheme-handler/app;application/cdf".to_string();
         let mut de = DesktopEntry::try_from(content).unwrap();
-        de.delete_scheme_handler();
+        de.delete_scheme_handler("x-scheme-handler/app");
         assert!(!de
             .data
             .get("MimeType")
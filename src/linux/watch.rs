@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{
+    default_handler_in, get_applications_dir, get_mimeapps_path, get_mimeapps_search_paths,
+    Environment, LinuxError, ProcessEnvironment,
+};
+use crate::{ProtocolHandler, RegistrationConfig};
+
+/// Keeps watching for default handler changes until dropped.
+pub struct DefaultWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+pub fn watch<F>(handler: &ProtocolHandler, callback: F) -> Result<DefaultWatcher, LinuxError>
+where
+    F: FnMut(Option<String>) + Send + 'static,
+{
+    watch_with_env(handler, &ProcessEnvironment, callback)
+}
+
+fn watch_with_env<F>(
+    handler: &ProtocolHandler,
+    env: &dyn Environment,
+    mut callback: F,
+) -> Result<DefaultWatcher, LinuxError>
+where
+    F: FnMut(Option<String>) + Send + 'static,
+{
+    let scheme: String = handler.protocol_name.clone();
+    let paths: Vec<PathBuf> = get_mimeapps_search_paths(env)?;
+    let mut current: Option<String> = default_handler_in(&paths, &scheme)?;
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if event.is_err() {
+            return;
+        }
+        match default_handler_in(&paths, &scheme) {
+            Ok(default) if default != current => {
+                current = default.clone();
+                callback(default);
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("Could not read the default handler: {err}"),
+        }
+    })?;
+
+    // Watch the directories rather than the files, editors and
+    // `xdg-mime` replace mimeapps.list instead of writing it in place.
+    let mimeapps_path: PathBuf = get_mimeapps_path(env)?;
+    let applications_dir: PathBuf = get_applications_dir(&RegistrationConfig::default(), env)?;
+    let dirs: [Option<&Path>; 2] = [mimeapps_path.parent(), Some(&applications_dir)];
+    for dir in dirs.into_iter().flatten() {
+        if dir.is_dir() {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+    }
+
+    Ok(DefaultWatcher { _watcher: watcher })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::mpsc, time::Duration};
+
+    use super::*;
+    use crate::linux::tests::FakeEnvironment;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_watch_default_change() {
+        let dir = tempdir().unwrap();
+        let home = dir.path().to_string_lossy().to_string();
+        let env = FakeEnvironment::new(&[("HOME", &home), ("XDG_CONFIG_DIRS", &home)]);
+        let config = dir.path().join(".config");
+        fs::create_dir_all(&config).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let handler = ProtocolHandler::new("myapp", "myapp");
+        let _watcher = watch_with_env(&handler, &env, move |default| {
+            sender.send(default).unwrap();
+        })
+        .unwrap();
+
+        fs::write(
+            config.join("mimeapps.list"),
+            "[Default Applications]\nx-scheme-handler/myapp=other.desktop\n",
+        )
+        .unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Some("other.desktop".to_string())
+        );
+    }
+}
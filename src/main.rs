@@ -1,9 +1,6 @@
 use protocol_handler::ProtocolHandler;
 
 fn main() {
-    let ph: ProtocolHandler = ProtocolHandler {
-        name: "myapp".to_string(),
-        protocol_name: "myapp".to_string(),
-    };
+    let ph: ProtocolHandler = ProtocolHandler::new("myapp", "myapp");
     ph.register().unwrap();
 }
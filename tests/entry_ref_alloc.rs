@@ -0,0 +1,48 @@
+#![cfg(all(target_os = "linux", feature = "linux"))]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use protocol_handler::linux::DesktopEntryRef;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn test_read_path_does_not_allocate() {
+    let source: String =
+        "[Desktop Entry]\nName=App\nExec=app %u\nMimeType=text/plain;x-scheme-handler/myapp"
+            .to_string();
+
+    let before: usize = ALLOCATIONS.with(|x| x.get());
+    let mut found: usize = 0;
+    for _ in 0..1000 {
+        let entry = DesktopEntryRef::new(&source).unwrap();
+        if entry.has_scheme_handler("myapp") && !entry.has_scheme_handler("other") {
+            found += 1;
+        }
+    }
+    let after: usize = ALLOCATIONS.with(|x| x.get());
+
+    assert_eq!(found, 1000);
+    assert_eq!(after - before, 0);
+}
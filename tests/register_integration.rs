@@ -0,0 +1,56 @@
+#![cfg(all(target_os = "linux", feature = "linux"))]
+
+use std::{env, fs};
+
+use protocol_handler::ProtocolHandler;
+use tempfile::tempdir;
+
+#[test]
+fn test_register_query_unregister() {
+    let home = tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    env::remove_var("XDG_DATA_HOME");
+    env::remove_var("XDG_CONFIG_HOME");
+
+    let path = home.path().join(".local/share/applications/myapp.desktop");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+    // Pin the executable so the test also passes inside containers.
+    let exe = env::current_exe().unwrap();
+    let first = ProtocolHandler::new("myapp", "first").host_exe(&exe);
+    let second = ProtocolHandler::new("myapp", "second").host_exe(&exe);
+    let exec = format!("{} %u", exe.display());
+
+    let managed = "X-ProtocolHandler-Managed=true";
+
+    assert!(first.register().unwrap().created);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("[Desktop Entry]\nType=Application\nName=myapp\nExec={exec}\nMimeType=x-scheme-handler/first\n{managed}")
+    );
+
+    assert!(!second.register().unwrap().created);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!(
+            "[Desktop Entry]\nType=Application\nName=myapp\nExec={exec}\nMimeType=x-scheme-handler/first;x-scheme-handler/second\n{managed}"
+        )
+    );
+    assert!(first.is_registered().unwrap());
+    assert!(second.is_registered().unwrap());
+
+    first.unregister().unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("[Desktop Entry]\nType=Application\nName=myapp\nExec={exec}\nMimeType=x-scheme-handler/second\n{managed}")
+    );
+    assert!(!first.is_registered().unwrap());
+    assert!(second.is_registered().unwrap());
+
+    second.unregister().unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("[Desktop Entry]\nType=Application\nName=myapp\nExec={exec}\n{managed}")
+    );
+    assert!(!home.path().join(".config/mimeapps.list").exists());
+}